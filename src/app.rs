@@ -1,28 +1,76 @@
 use meivm2::opcode::{Opcode, RegIndex};
-use meivm2::{FlightModule, MEM_SHARED_SIZE_U, Ship};
+use meivm2::{MEM_SHARED_SIZE_U, Ship};
 use ratatui::crossterm::event;
 use ratatui::layout::{Alignment, Margin, Position, Rect};
 use ratatui::style::{Color, Modifier, Style, Stylize};
-use ratatui::text::Line;
+use ratatui::text::{Line, Span};
+use ratatui::symbols::Marker;
+use ratatui::widgets::canvas::{self, Canvas, Points};
 use ratatui::widgets::{Block, BorderType, Clear, Padding, Paragraph};
 use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::time::Duration;
 
-use crate::modules::Module;
+use crate::assembler::{assemble, assemble_line, disassemble};
+use crate::breakpoints::{parse_trace_message, Access, Breakpoint, BreakpointKind, Expr, Watchpoint};
+use crate::map::{self, MapView};
+use crate::modules::{angle_turns, to_angle, to_fixed, FlightState, Module, ModuleDefs};
+use crate::profile::Profile;
+use crate::coverage::{self, Coverage};
+use crate::snapshot::describe_users;
+use crate::symbols::SymbolTable;
 use crate::{sim, SimCommand, SimOutput, S};
 use crate::utils::*;
 use crate::wavebin::*;
 
-use clap::Parser as _;
-
 #[derive(clap::Parser)]
 #[command(about = "WaveVM Assembly Compiler", long_about = None)]
-struct Cli {
+pub struct Cli {
+  #[command(subcommand)]
+  pub command: Option<CliCommand>,
   /// Input file to load into memory
   #[arg()]
-  infile: Option<PathBuf>,
+  pub infile: Option<PathBuf>,
+  /// Run without the TUI and exit when the program stops.
+  /// Exit codes: 0 halted, 1 error, 2 breakpoint or watchpoint, 3 tick limit
+  #[arg(long)]
+  pub headless: bool,
+  /// Maximum number of ticks to run in headless mode
+  #[arg(long, default_value_t = 100000)]
+  pub ticks: usize,
+  /// Breakpoint address in hex (headless mode, repeatable)
+  #[arg(long = "break", value_name = "ADDR", value_parser = parse_hex_arg)]
+  pub breakpoints: Vec<u16>,
+  /// Memory range to print after a headless run, in hex (repeatable)
+  #[arg(long, value_name = "ADDR[:LEN]", value_parser = parse_range_arg)]
+  pub dump: Vec<(u16, u16)>,
+  /// Print the headless report as JSON
+  #[arg(long)]
+  pub json: bool,
+  /// Record every executed instruction to this file (headless mode)
+  #[arg(long, value_name = "FILE")]
+  pub trace: Option<PathBuf>,
+  /// Number of ticks recorded for reverse stepping (off by default, `history <n>` enables it later)
+  #[arg(long, default_value_t = 0)]
+  pub history: usize,
+  /// Module definition file to load on top of the built-in modules (repeatable)
+  #[arg(long = "modules", value_name = "FILE")]
+  pub module_files: Vec<PathBuf>,
+}
+
+#[derive(clap::Subcommand)]
+pub enum CliCommand {
+  /// Compare two recorded traces and report where the runs diverge.
+  /// Exit codes: 0 identical, 1 error, 2 different
+  TraceDiff {
+    a: PathBuf,
+    b: PathBuf,
+    /// Number of records to show around the first difference
+    #[arg(long, default_value_t = 5)]
+    context: usize,
+  },
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
@@ -32,11 +80,16 @@ pub enum InputMode {
   Command,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ViewMode {
   Log,
   Memory,
   Code,
+  Profile,
+  Users,
+  Modules,
+  Map,
+  Instruments,
 }
 
 impl ViewMode {
@@ -44,15 +97,25 @@ impl ViewMode {
     match self {
       ViewMode::Log => ViewMode::Memory,
       ViewMode::Memory => ViewMode::Code,
-      ViewMode::Code => ViewMode::Log,
+      ViewMode::Code => ViewMode::Profile,
+      ViewMode::Profile => ViewMode::Users,
+      ViewMode::Users => ViewMode::Modules,
+      ViewMode::Modules => ViewMode::Map,
+      ViewMode::Map => ViewMode::Instruments,
+      ViewMode::Instruments => ViewMode::Log,
     }
   }
 
   fn prev(self) -> Self {
     match self {
-      ViewMode::Log => ViewMode::Code,
+      ViewMode::Log => ViewMode::Instruments,
       ViewMode::Memory => ViewMode::Log,
       ViewMode::Code => ViewMode::Memory,
+      ViewMode::Profile => ViewMode::Code,
+      ViewMode::Users => ViewMode::Profile,
+      ViewMode::Modules => ViewMode::Users,
+      ViewMode::Map => ViewMode::Modules,
+      ViewMode::Instruments => ViewMode::Map,
     }
   }
 }
@@ -66,6 +129,29 @@ pub struct SimState {
   active_user: u64,
 }
 
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum UserState {
+  Running,
+  Halted,
+  /// Running in debug mode, stepped by hand.
+  Debug,
+  Breakpoint,
+}
+
+/// One row of the Users view.
+#[derive(Debug, Clone)]
+pub struct UserStatus {
+  /// None for a process whose user ID is unknown. Its PC can't be read then.
+  pub user: Option<u64>,
+  pub state: UserState,
+  /// The user is in the VM's process list.
+  pub scheduled: bool,
+  pub pc: u16,
+  pub word: u16,
+  pub sleep: u32,
+  pub pos: (f32, f32),
+}
+
 #[derive(Debug)]
 pub struct SimStateUpdate {
   pub running: bool,
@@ -77,10 +163,15 @@ pub struct SimStateUpdate {
 
 pub struct App {
   sim_state: SimState,
-  ship: Ship,
+  ships: BTreeMap<u64, Ship>,
+  /// Ships of scheduled processes without a known user ID.
+  other_ships: Vec<Ship>,
+  map: MapView,
+  module_defs: ModuleDefs,
   ui_regions: UIRegions,
   mouse_pos: Option<Position>,
   mouse_clicks: Vec<Position>,
+  mouse_right_clicks: Vec<Position>,
   // mouse_drag: Option<(Position, Position)>,
   mouse_drops: Vec<(Position, Position)>,
   view_mode: ViewMode,
@@ -92,13 +183,29 @@ pub struct App {
   code_scroll: usize,
   code_offset: usize,
   memory_scroll: usize,
-  breakpoints: Vec<u16>,
+  breakpoints: Vec<Breakpoint>,
+  watchpoints: Vec<Watchpoint>,
   watch_addr: Vec<(u16, u16, Option<String>)>,
+  symbols: SymbolTable,
+  /// Ticks of recorded history and how many of them have been undone.
+  history: (usize, usize),
+  profile: Profile,
+  profile_scroll: usize,
+  coverage: Coverage,
+  /// Last program loaded, named as the source file in coverage reports.
+  program_path: Option<PathBuf>,
+  users: Vec<UserStatus>,
+  /// Scheduled processes that belong to no known user.
+  users_selected: usize,
+  modules_selected: usize,
   actions: Vec<AppActions>,
 }
 
 pub enum AppActions {
+  RunToCursor(u16),
   Breakpoint(u16),
+  SetUser(u64),
+  Write(u16, u16),
 }
 
 impl App {
@@ -112,13 +219,14 @@ impl App {
         debug_mode: false,
         active_user: 0,
       },
-      ship: Ship {
-        flight: FlightModule::default(),
-        ..Default::default()
-      },
+      ships: BTreeMap::new(),
+      other_ships: Vec::new(),
+      map: MapView::default(),
+      module_defs: ModuleDefs::default(),
       ui_regions: UIRegions::default(),
       mouse_pos: None,
       mouse_clicks: Vec::new(),
+      mouse_right_clicks: Vec::new(),
       // mouse_drag: None,
       mouse_drops: Vec::new(),
       view_mode: ViewMode::Code,
@@ -131,19 +239,27 @@ impl App {
       code_offset: 0x40,
       memory_scroll: 0,
       breakpoints: Vec::new(),
+      watchpoints: Vec::new(),
       watch_addr: vec![
         (0x380, 0x8, Some(S!("Ship"))),
         (0x3c0, 0x8, Some(S!("NAV"))),
         (0x80, 0x20, None),
         (0x1000, 0x30, Some(S!("Public Memory"))),
       ],
+      symbols: SymbolTable::default(),
+      history: (0, 0),
+      profile: Profile::default(),
+      profile_scroll: 0,
+      coverage: Coverage::default(),
+      program_path: None,
+      users: Vec::new(),
+      users_selected: 0,
+      modules_selected: 0,
       actions: Vec::new(),
     }
   }
 
-  pub fn run(&mut self, mut terminal: DefaultTerminal) -> Result<(), Box<dyn std::error::Error>> {
-    let args = Cli::parse();
-
+  pub fn run(&mut self, mut terminal: DefaultTerminal, args: Cli) -> Result<(), Box<dyn std::error::Error>> {
     let (sim_channel_tx, sim_channel_rx) = mpsc::sync_channel(16);
     let (sim_output_tx, sim_output_rx) = mpsc::channel();
     std::thread::spawn(|| {
@@ -157,18 +273,22 @@ impl App {
     // });
 
     sim_channel_tx.send(SimCommand::Reset)?;
+    sim_channel_tx.send(SimCommand::HistoryDepth(args.history))?;
     sim_channel_tx.send(SimCommand::Debug(true))?;
     sim_channel_tx.send(SimCommand::Run)?;
 
     if let Some(infile) = args.infile {
       let infile = infile.to_str().unwrap();
-      let bin = load_wavevm_bin(infile);
+      let bin = load_program(infile);
       match bin {
         Ok(bin) => {
           // sim_channel_tx.send(SimCommand::Summon)?;
           // sim_channel_tx.send(SimCommand::SetUser(0))?;
-          sim_channel_tx.send(SimCommand::WriteAll(0, bin.mem))?;
-          sim_channel_tx.send(SimCommand::WriteAll(0x40, bin.code))?;
+          self.symbols = bin.symbols;
+          self.program_path = Some(PathBuf::from(infile));
+          for section in bin.sections {
+            sim_channel_tx.send(SimCommand::WriteAll(section.addr, section.data))?;
+          }
         }
         Err(err) => {
           self.print_plain(format!("Failed to load file: {}", err));
@@ -176,10 +296,21 @@ impl App {
       }
     }
 
+    for path in args.module_files.iter() {
+      let path = path.to_str().unwrap();
+      match self.module_defs.load(path) {
+        Ok(names) => self.print_plain(format!("Loaded modules {} from {}", names.join(", "), path)),
+        Err(err) => self.print_plain(format!("Failed to load {}: {}", path, err)),
+      }
+    }
+
     sim_channel_tx.send(SimCommand::Halt)?;
     sim_channel_tx.send(SimCommand::Debug(false))?;
 
     let mut mouse_down: Position = Position { x: 0, y: 0 };
+    // Whether the sim currently sends profiler snapshots and Users rows.
+    let mut profile_shown = false;
+    let mut users_shown = false;
 
     loop {
       terminal.draw(|frame| self.draw(frame))?;
@@ -193,16 +324,40 @@ impl App {
       use event::KeyModifiers as M;
       let mut exit = false;
 
+      // Only the Profile and Code views show profiler counts.
+      let show_profile = matches!(self.view_mode, ViewMode::Profile | ViewMode::Code);
+      if show_profile != profile_shown {
+        profile_shown = show_profile;
+        sim_channel_tx.send(SimCommand::ShowProfile(show_profile))?;
+      }
+      let show_users = self.view_mode == ViewMode::Users;
+      if show_users != users_shown {
+        users_shown = show_users;
+        sim_channel_tx.send(SimCommand::ShowUsers(show_users))?;
+      }
+
       while let Some(action) = self.actions.pop() {
         match action {
           AppActions::Breakpoint(addr) => {
-            if let Some(i) = self.breakpoints.iter().position(|&x| x == addr) {
+            if let Some(i) = self.breakpoints.iter().position(|bp| bp.addr == addr) {
               self.breakpoints.remove(i);
             } else {
-              self.breakpoints.push(addr);
+              self.breakpoints.push(Breakpoint::new(addr));
             }
             sim_channel_tx.send(SimCommand::Breakpoints(self.breakpoints.clone()))?;
           }
+          AppActions::RunToCursor(addr) => {
+            self.sim_state.running = true;
+            self.sim_state.debug_mode = false;
+            sim_channel_tx.send(SimCommand::RunUntil(addr))?;
+          }
+          AppActions::SetUser(user) => {
+            sim_channel_tx.send(SimCommand::SetUser(user))?;
+            self.sim_state.active_user = user;
+          }
+          AppActions::Write(addr, val) => {
+            sim_channel_tx.send(SimCommand::Write(addr, val))?;
+          }
         }
       }
 
@@ -221,11 +376,49 @@ impl App {
               (Menu, K::Char(' ')) => { self.input_mode = InputMode::Command; }
               (Menu, K::Char('r')) => { self.sim_state.running = true; sim_channel_tx.send(SimCommand::Run)?; }
               (Menu, K::Char('s')) => { self.sim_state.running = false; self.sim_state.debug_mode = true; sim_channel_tx.send(SimCommand::Step)?; }
+              (Menu, K::Char('n')) => { sim_channel_tx.send(SimCommand::Next)?; }
+              (Menu, K::Char('f')) => { sim_channel_tx.send(SimCommand::Finish)?; }
               (Menu, K::Char('R')) => { self.sim_state.running = false; sim_channel_tx.send(SimCommand::Halt)?; }
               (Menu, K::Char('d')) => { self.sim_state.debug_mode = !self.sim_state.debug_mode; sim_channel_tx.send(SimCommand::Debug(self.sim_state.debug_mode))?; }
               (Menu, K::Char('e')) => { self.sim_state.running = false; sim_channel_tx.send(SimCommand::Restart)?; }
               (Menu, K::Tab) => { self.view_mode = self.view_mode.next(); }
               (Menu, K::BackTab) => { self.view_mode = self.view_mode.prev(); }
+              (Menu, K::Up) if self.view_mode == ViewMode::Users => { self.users_selected = self.users_selected.saturating_sub(1); }
+              (Menu, K::Down) if self.view_mode == ViewMode::Users => {
+                self.users_selected = (self.users_selected + 1).min(self.users.len().saturating_sub(1));
+              }
+              (Menu, K::Up) if self.view_mode == ViewMode::Modules => { self.modules_selected = self.modules_selected.saturating_sub(1); }
+              (Menu, K::Down) if self.view_mode == ViewMode::Modules => { self.modules_selected = (self.modules_selected + 1).min(7); }
+              (Menu, K::Enter) if self.view_mode == ViewMode::Modules => { self.show_slot(self.modules_selected); }
+              (Menu, K::Char('+' | '=')) if self.view_mode == ViewMode::Map => { self.map.zoom_by(1.25); }
+              (Menu, K::Char('-')) if self.view_mode == ViewMode::Map => { self.map.zoom_by(0.8); }
+              (Menu, K::Left) if self.view_mode == ViewMode::Map => { self.map.pan(-0.125, 0.0); }
+              (Menu, K::Right) if self.view_mode == ViewMode::Map => { self.map.pan(0.125, 0.0); }
+              (Menu, K::Up) if self.view_mode == ViewMode::Map => { self.map.pan(0.0, -0.125); }
+              (Menu, K::Down) if self.view_mode == ViewMode::Map => { self.map.pan(0.0, 0.125); }
+              (Menu, K::Char('c')) if self.view_mode == ViewMode::Map => {
+                if let Some(ship) = self.ships.get(&self.sim_state.active_user) {
+                  self.map.center = (ship.phy.pos.x, ship.phy.pos.y);
+                }
+              }
+              (Menu, K::Left) if self.view_mode == ViewMode::Instruments => { self.steer(0.0, (-0.25, 0.0)); }
+              (Menu, K::Right) if self.view_mode == ViewMode::Instruments => { self.steer(0.0, (0.25, 0.0)); }
+              (Menu, K::Up) if self.view_mode == ViewMode::Instruments => { self.steer(0.0, (0.0, -0.25)); }
+              (Menu, K::Down) if self.view_mode == ViewMode::Instruments => { self.steer(0.0, (0.0, 0.25)); }
+              (Menu, K::Char('[')) if self.view_mode == ViewMode::Instruments => { self.steer(-1.0 / 32.0, (0.0, 0.0)); }
+              (Menu, K::Char(']')) if self.view_mode == ViewMode::Instruments => { self.steer(1.0 / 32.0, (0.0, 0.0)); }
+              (Menu, K::Char('0')) if self.view_mode == ViewMode::Instruments => {
+                if let Some((module, _)) = self.flight_module() {
+                  self.write_named(module, FlightState::RRVX, 0);
+                  self.write_named(module, FlightState::RRVY, 0);
+                }
+              }
+              (Menu, K::Enter) if self.view_mode == ViewMode::Users => {
+                if let Some(status) = self.users.get(self.users_selected)
+                  && let Some(user) = status.user {
+                  self.actions.push(AppActions::SetUser(user));
+                }
+              }
               (Command, K::Tab) => { /* tab completion */ }
               (Command, K::BackTab) => { /* tab completion? */ }
               (Command, K::Esc) => { self.clear_input(); self.input_mode = InputMode::Menu; }
@@ -282,7 +475,7 @@ impl App {
                       match self.view_mode {
                         ViewMode::Code => {
                           if let Some(addr) = split.peek() {
-                            if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                            if let Some(addr) = self.parse_addr(addr) {
                               // self.code_scroll = addr as usize;
                               self.code_offset = addr as usize;
                               split.next();
@@ -293,7 +486,7 @@ impl App {
                         }
                         ViewMode::Memory => {
                           if let Some(addr) = split.peek() {
-                            if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                            if let Some(addr) = self.parse_addr(addr) {
                               self.memory_scroll = addr as usize;
                               split.next();
                             } else {
@@ -301,7 +494,7 @@ impl App {
                             }
                           }
                         }
-                        ViewMode::Log => {
+                        ViewMode::Log | ViewMode::Profile | ViewMode::Users | ViewMode::Modules | ViewMode::Map | ViewMode::Instruments => {
 
                         }
                       }
@@ -315,23 +508,83 @@ impl App {
                       };
 
                       if file_path.exists() {
-                        let bin = load_wavevm_bin(file_path.to_str().unwrap());
+                        let bin = load_program(file_path.to_str().unwrap());
                         match bin {
                           Ok(bin) => {
-                            let mlen = bin.mem.len();
-                            let clen = bin.code.len();
-                            sim_channel_tx.send(SimCommand::WriteAll(0, bin.mem))?;
-                            sim_channel_tx.send(SimCommand::WriteAll(0x40, bin.code))?;
-                            self.print_plain(format!("Loaded {} bytes of memory and {} bytes of code from {}", mlen, clen, file_path.display()));
+                            self.print_plain(format!("Loaded {} sections and {} symbols from {} (v{})", bin.sections.len(), bin.symbols.len(), file_path.display(), bin.version));
+                            self.symbols = bin.symbols;
+                            self.program_path = Some(file_path.clone());
+                            for section in bin.sections {
+                              self.print_plain(format!("  {:<6} @{:04x}: {} words", section.kind(), section.addr, section.data.len()));
+                              sim_channel_tx.send(SimCommand::WriteAll(section.addr, section.data))?;
+                            }
                           }
                           Err(msg) => {
-                            err = Some(format!("Failed to load file: {}", msg));
+                            error!("Failed to load {}: {:?}", file_path.display(), msg);
+                            err = Some(format!("Failed to load {}: {}", file_path.display(), msg));
                           }
                         }
                       } else {
                         err = Some(format!("File not found: {}", file_path.display()));
                       }
                     }
+                    "save" => {
+                      let file_path = if let Some(path) = split.next() {
+                        PathBuf::from(path)
+                      } else {
+                        err = Some(S!("No file path provided."));
+                        continue;
+                      };
+                      let mut start = 0;
+                      let mut len = MEM_SHARED_SIZE_U;
+                      let usage = "Usage: save <file> [start [len]], with start and len in hex";
+                      if let Some(addr) = split.next() {
+                        let Ok(addr) = u16::from_str_radix(addr, 16) else {
+                          err = Some(S!(usage));
+                          continue;
+                        };
+                        start = (addr as usize).min(MEM_SHARED_SIZE_U);
+                        len = MEM_SHARED_SIZE_U - start;
+                        if let Some(size) = split.next() {
+                          let Ok(size) = u16::from_str_radix(size, 16) else {
+                            err = Some(S!(usage));
+                            continue;
+                          };
+                          len = size as usize;
+                        }
+                      }
+
+                      let bin = WaveVMBin::from_memory(&self.sim_state.memory, start, len);
+                      match write_wavevm_bin(file_path.to_str().unwrap(), &bin) {
+                        Ok(()) => {
+                          output_lines.push(format!("Saved {} words in {} sections to {}", bin.word_count(), bin.sections.len(), file_path.display()));
+                          if !self.symbols.is_empty() {
+                            let sym_path = format!("{}.sym", file_path.display());
+                            if let Err(msg) = std::fs::write(&sym_path, self.symbols.serialize()) {
+                              err = Some(format!("Failed to save {}: {}", sym_path, msg));
+                            }
+                          }
+                        }
+                        Err(msg) => {
+                          err = Some(format!("Failed to save {}: {}", file_path.display(), msg));
+                        }
+                      }
+                    }
+                    "sym" | "symbols" => {
+                      if let Some(path) = split.next() {
+                        match SymbolTable::load(path) {
+                          Ok(symbols) => {
+                            output_lines.push(format!("Loaded {} symbols from {}", symbols.len(), path));
+                            self.symbols.merge(symbols);
+                          }
+                          Err(msg) => {
+                            err = Some(format!("Failed to load {}: {}", path, msg));
+                          }
+                        }
+                      } else {
+                        err = Some(S!("No file path provided."));
+                      }
+                    }
                     "reset" | "clear" => {
                       sim_channel_tx.send(SimCommand::Reset)?;
                     }
@@ -342,18 +595,111 @@ impl App {
                       sim_channel_tx.send(SimCommand::WriteCommand(self.input_string.clone()))?;
                     }
                     "code" => {
-                      sim_channel_tx.send(SimCommand::CodeCommand(self.input_string.clone()))?;
+                      // The rest of the line is assembled from 0x40, with `|` between statements.
+                      let source = split.by_ref().collect::<Vec<_>>().join(" ").replace('|', "\n");
+                      match assemble(&source) {
+                        Ok(sections) => {
+                          let words = sections.iter().map(|section| section.data.len()).sum::<usize>();
+                          for section in sections {
+                            sim_channel_tx.send(SimCommand::WriteAll(section.addr, section.data))?;
+                          }
+                          output_lines.push(format!("Assembled {} words", words));
+                        }
+                        Err(msg) => {
+                          err = Some(format!("Failed to assemble: {}", msg));
+                        }
+                      }
+                    }
+                    "asm" => {
+                      let addr = if let Some(addr) = split.next() && let Some(addr) = self.parse_addr(addr) {
+                        addr
+                      } else {
+                        err = Some(S!("Invalid address. Usage: asm <addr> <instruction>"));
+                        continue;
+                      };
+                      // The instruction is the rest of the line.
+                      let text = split.by_ref().collect::<Vec<_>>().join(" ");
+                      match assemble_line(addr, &text) {
+                        Ok(words) => {
+                          let words_str = words.iter().map(|w| format!("{:04x}", w)).collect::<Vec<_>>().join(" ");
+                          output_lines.push(format!("@{:04x}: {}", addr, words_str));
+                          sim_channel_tx.send(SimCommand::WriteAll(addr, words))?;
+                        }
+                        Err(msg) => {
+                          err = Some(format!("Failed to assemble: {}", msg.message));
+                        }
+                      }
+                    }
+                    "disasm" => {
+                      let range = (split.next().and_then(|a| self.parse_addr(a)), split.next().and_then(|a| self.parse_addr(a)));
+                      let (start, end) = if let (Some(start), Some(end)) = range {
+                        (start, end)
+                      } else {
+                        err = Some(S!("Invalid range. Usage: disasm <start> <end> [file]"));
+                        continue;
+                      };
+                      let listing = disassemble(&self.sim_state.memory, start, end);
+                      if let Some(path) = split.next() {
+                        match std::fs::write(path, &listing) {
+                          Ok(()) => output_lines.push(format!("Wrote disassembly of {:04x}..{:04x} to {}", start, end, path)),
+                          Err(msg) => err = Some(format!("Failed to write {}: {}", path, msg)),
+                        }
+                      } else {
+                        output_lines.extend(listing.lines().map(String::from));
+                      }
                     }
                     "bp" | "breakpoint" => {
+                      if split.peek() == Some(&"list") {
+                        split.next();
+                        for bp in self.breakpoints.iter() {
+                          output_lines.push(bp.to_string());
+                        }
+                        continue;
+                      }
                       if let Some(addr) = split.peek() {
-                        if let Ok(addr) = u16::from_str_radix(addr, 16) {
-                          // Check if breakpoint exists
-                          if let Some(i) = self.breakpoints.iter().position(|&x| x == addr) {
-                            self.breakpoints.remove(i);
+                        if let Some(addr) = self.parse_addr(addr) {
+                          split.next();
+                          let mut ignore_count = None;
+                          let mut condition = None;
+                          if split.peek() == Some(&"ignore") {
                             split.next();
-                          } else {
-                            self.breakpoints.push(addr);
+                            if let Some(count) = split.next().and_then(|n| n.parse::<u32>().ok()) {
+                              ignore_count = Some(count);
+                            } else {
+                              err = Some(S!("Invalid ignore count. Usage: bp <addr> ignore <n>"));
+                              continue;
+                            }
+                          }
+                          if split.peek() == Some(&"if") {
                             split.next();
+                            // The condition is the rest of the line.
+                            let text = split.by_ref().collect::<Vec<_>>().join(" ");
+                            match Expr::parse(&text, &|name| self.symbols.lookup(name)) {
+                              Ok(expr) => condition = Some((text, expr)),
+                              Err(msg) => {
+                                err = Some(format!("Invalid condition: {}", msg));
+                                continue;
+                              }
+                            }
+                          }
+
+                          let existing = self.breakpoints.iter().position(|bp| bp.addr == addr);
+                          if ignore_count.is_none() && condition.is_none() {
+                            // Check if breakpoint exists
+                            if let Some(i) = existing {
+                              self.breakpoints.remove(i);
+                            } else {
+                              self.breakpoints.push(Breakpoint::new(addr));
+                            }
+                          } else {
+                            let mut bp = existing
+                              .map(|i| self.breakpoints.remove(i))
+                              .unwrap_or_else(|| Breakpoint::new(addr));
+                            bp.ignore_count = ignore_count.unwrap_or(bp.ignore_count);
+                            bp.condition = condition.or(bp.condition);
+                            bp.hits = 0;
+                            output_lines.push(bp.to_string());
+                            self.breakpoints.push(bp);
                           }
 
                           sim_channel_tx.send(SimCommand::Breakpoints(self.breakpoints.clone()))?;
@@ -363,6 +709,88 @@ impl App {
                         }
                       }
                     }
+                    "wp" | "watchpoint" => {
+                      if split.peek() == Some(&"list") {
+                        split.next();
+                        for wp in self.watchpoints.iter() {
+                          output_lines.push(wp.to_string());
+                        }
+                        continue;
+                      }
+                      let addr = if let Some(addr) = split.next() && let Some(addr) = self.parse_addr(addr) {
+                        addr
+                      } else {
+                        err = Some(S!("Invalid address. Usage: wp <addr> [len] [r|w|rw] [if <cond>]"));
+                        continue;
+                      };
+                      let existing = self.watchpoints.iter().position(|wp| wp.addr == addr);
+                      if split.peek().is_none() && let Some(i) = existing {
+                        let wp = self.watchpoints.remove(i);
+                        output_lines.push(format!("Removed watchpoint {}", wp));
+                        sim_channel_tx.send(SimCommand::Watchpoints(self.watchpoints.clone()))?;
+                        continue;
+                      }
+
+                      let mut len = 1;
+                      if let Some(arg) = split.peek() && let Ok(n) = u16::from_str_radix(arg, 16) {
+                        len = n;
+                        split.next();
+                      }
+                      let mut access = Access::Write;
+                      if let Some(arg) = split.peek() && let Some(a) = Access::parse(arg) {
+                        access = a;
+                        split.next();
+                      }
+                      let mut wp = Watchpoint::new(addr, len, access);
+                      match split.next() {
+                        Some("if") => {
+                          // The condition is the rest of the line.
+                          let text = split.by_ref().collect::<Vec<_>>().join(" ");
+                          match Expr::parse(&text, &|name| self.symbols.lookup(name)) {
+                            Ok(expr) => wp.condition = Some((text, expr)),
+                            Err(msg) => {
+                              err = Some(format!("Invalid condition: {}", msg));
+                              continue;
+                            }
+                          }
+                        }
+                        Some(arg) => {
+                          err = Some(format!("Unexpected '{}'. Usage: wp <addr> [len] [r|w|rw] [if <cond>]", arg));
+                          continue;
+                        }
+                        None => (),
+                      }
+
+                      if let Some(i) = existing {
+                        self.watchpoints.remove(i);
+                      }
+                      output_lines.push(format!("Watchpoint {}", wp));
+                      self.watchpoints.push(wp);
+                      sim_channel_tx.send(SimCommand::Watchpoints(self.watchpoints.clone()))?;
+                    }
+                    "tp" | "tracepoint" => {
+                      let addr = if let Some(addr) = split.next() && let Some(addr) = self.parse_addr(addr) {
+                        addr
+                      } else {
+                        err = Some(S!("Invalid address. Usage: tp <addr> <message>"));
+                        continue;
+                      };
+                      // The message is the rest of the line; `{expr}` parts are evaluated on each hit.
+                      let text = split.by_ref().collect::<Vec<_>>().join(" ");
+                      match parse_trace_message(&text, &|name| self.symbols.lookup(name)) {
+                        Ok(parts) => {
+                          self.breakpoints.retain(|bp| bp.addr != addr);
+                          let mut bp = Breakpoint::new(addr);
+                          bp.kind = BreakpointKind::Trace(text, parts);
+                          output_lines.push(bp.to_string());
+                          self.breakpoints.push(bp);
+                          sim_channel_tx.send(SimCommand::Breakpoints(self.breakpoints.clone()))?;
+                        }
+                        Err(msg) => {
+                          err = Some(format!("Invalid message: {}", msg));
+                        }
+                      }
+                    }
                     "watch" => {
                       if let Some(sub) = split.peek() {
                         match sub {
@@ -398,7 +826,7 @@ impl App {
                     }
                     "peek" => {
                       if let Some(addr) = split.peek() {
-                        if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                        if let Some(addr) = self.parse_addr(addr) {
                           sim_channel_tx.send(SimCommand::Read(addr))?;
                           split.next();
                         } else {
@@ -476,6 +904,178 @@ impl App {
                         }
                       }
                     }
+                    "coverage" => {
+                      match (split.next(), split.next()) {
+                        (Some("reset"), None) => {
+                          sim_channel_tx.send(SimCommand::CoverageReset)?;
+                        }
+                        (Some("on"), None) => {
+                          sim_channel_tx.send(SimCommand::CoverageEnable(true))?;
+                        }
+                        (Some("off"), None) => {
+                          sim_channel_tx.send(SimCommand::CoverageEnable(false))?;
+                        }
+                        (Some("report"), file) => {
+                          let memory = &self.sim_state.memory;
+                          match file {
+                            Some(file) if file.ends_with(".info") || file.ends_with(".lcov") => {
+                              let source = self.program_path.as_ref().map(|p| p.display().to_string()).unwrap_or(S!("memory"));
+                              let report = coverage::report_lcov(&self.coverage, memory, &self.symbols, &source);
+                              match std::fs::write(file, report) {
+                                Ok(()) => output_lines.push(format!("Wrote lcov coverage to {}", file)),
+                                Err(e) => err = Some(format!("Failed to write {}: {}", file, e)),
+                              }
+                            }
+                            Some(file) => {
+                              let report = coverage::report_text(&self.coverage, memory, &self.symbols);
+                              match std::fs::write(file, report) {
+                                Ok(()) => output_lines.push(format!("Wrote coverage report to {}", file)),
+                                Err(e) => err = Some(format!("Failed to write {}: {}", file, e)),
+                              }
+                            }
+                            None => {
+                              let report = coverage::report_text(&self.coverage, memory, &self.symbols);
+                              output_lines.extend(report.lines().map(String::from));
+                            }
+                          }
+                        }
+                        _ => {
+                          err = Some(S!("Usage: coverage on | coverage off | coverage report [file] | coverage reset"));
+                        }
+                      }
+                    }
+                    "profile" => {
+                      match split.next() {
+                        Some("reset") => {
+                          sim_channel_tx.send(SimCommand::ProfileReset)?;
+                        }
+                        Some("on") => {
+                          sim_channel_tx.send(SimCommand::ProfileEnable(true))?;
+                        }
+                        Some("off") => {
+                          sim_channel_tx.send(SimCommand::ProfileEnable(false))?;
+                        }
+                        None => {
+                          self.view_mode = ViewMode::Profile;
+                        }
+                        Some(arg) => {
+                          err = Some(format!("Unknown profile command '{}'. Usage: profile [on | off | reset]", arg));
+                        }
+                      }
+                    }
+                    "instruments" => {
+                      self.view_mode = ViewMode::Instruments;
+                    }
+                    "map" => {
+                      let usage = "Usage: map [zoom <factor> | trail <positions> | predict <ticks> | center]";
+                      match (split.next(), split.next()) {
+                        (None, _) => {
+                          self.view_mode = ViewMode::Map;
+                        }
+                        (Some("zoom"), Some(zoom)) if zoom.parse::<f32>().is_ok_and(|zoom| zoom > 0.0) => {
+                          self.map.zoom = 1.0;
+                          self.map.zoom_by(zoom.parse().unwrap());
+                        }
+                        (Some("trail"), Some(len)) if len.parse::<usize>().is_ok() => {
+                          self.map.trail_len = len.parse().unwrap();
+                        }
+                        (Some("predict"), Some(ticks)) if ticks.parse::<u32>().is_ok() => {
+                          self.map.predict_ticks = ticks.parse().unwrap();
+                        }
+                        (Some("center"), None) => {
+                          self.map.center = MapView::default().center;
+                        }
+                        _ => {
+                          err = Some(S!(usage));
+                        }
+                      }
+                    }
+                    "modules" => {
+                      match (split.next(), split.next()) {
+                        (None, _) => {
+                          self.view_mode = ViewMode::Modules;
+                        }
+                        (Some("load"), Some(path)) => {
+                          match self.module_defs.load(path) {
+                            Ok(names) => output_lines.push(format!("Loaded modules {} from {}", names.join(", "), path)),
+                            Err(msg) => err = Some(format!("Failed to load {}: {}", path, msg)),
+                          }
+                        }
+                        _ => {
+                          err = Some(S!("Usage: modules [load <file>]"));
+                        }
+                      }
+                    }
+                    "snapshot" => {
+                      let usage = "Usage: snapshot save <name> [all] | snapshot load <name>";
+                      // A bare name gets the default extension.
+                      let path = |name: &str| if name.contains('.') { name.to_string() } else { format!("{}.snap", name) };
+                      match (split.next(), split.next(), split.next()) {
+                        (Some("save"), Some(name), None) => {
+                          sim_channel_tx.send(SimCommand::SnapshotSave(path(name), false))?;
+                        }
+                        (Some("save"), Some(name), Some("all")) => {
+                          sim_channel_tx.send(SimCommand::SnapshotSave(path(name), true))?;
+                        }
+                        (Some("load"), Some(name), None) => {
+                          sim_channel_tx.send(SimCommand::SnapshotLoad(path(name)))?;
+                        }
+                        _ => {
+                          err = Some(S!(usage));
+                        }
+                      }
+                    }
+                    "trace" => {
+                      match (split.next(), split.next()) {
+                        (Some("on"), Some(file)) => {
+                          sim_channel_tx.send(SimCommand::Trace(Some(file.to_string())))?;
+                          output_lines.push(format!("Tracing to {}", file));
+                        }
+                        (Some("off"), None) => {
+                          sim_channel_tx.send(SimCommand::Trace(None))?;
+                        }
+                        _ => {
+                          err = Some(S!("Usage: trace on <file> | trace off"));
+                        }
+                      }
+                    }
+                    "rs" | "rstep" => {
+                      self.sim_state.running = false;
+                      self.sim_state.debug_mode = true;
+                      sim_channel_tx.send(SimCommand::ReverseStep)?;
+                    }
+                    "rc" | "rcontinue" => {
+                      self.sim_state.running = false;
+                      self.sim_state.debug_mode = true;
+                      sim_channel_tx.send(SimCommand::ReverseContinue)?;
+                    }
+                    "history" => {
+                      if let Some(depth) = split.next() {
+                        if let Ok(depth) = depth.parse::<usize>() {
+                          sim_channel_tx.send(SimCommand::HistoryDepth(depth))?;
+                          output_lines.push(format!("Recording {} ticks of history", depth));
+                        } else {
+                          err = Some(S!("Invalid depth. Usage: history <ticks>"));
+                        }
+                      } else {
+                        output_lines.push(format!("{} ticks recorded, {} undone", self.history.0, self.history.1));
+                      }
+                    }
+                    "n" | "next" => {
+                      sim_channel_tx.send(SimCommand::Next)?;
+                    }
+                    "finish" => {
+                      sim_channel_tx.send(SimCommand::Finish)?;
+                    }
+                    "until" => {
+                      if let Some(addr) = split.next() && let Some(addr) = self.parse_addr(addr) {
+                        self.sim_state.running = true;
+                        self.sim_state.debug_mode = false;
+                        sim_channel_tx.send(SimCommand::RunUntil(addr))?;
+                      } else {
+                        err = Some(S!("Invalid address. Usage: until <addr>"));
+                      }
+                    }
                     "s" | "step" | "tick" => {
                       // sim_channel_tx.send(SimCommand::Run)?;
                       self.sim_state.running = false;
@@ -522,6 +1122,9 @@ impl App {
                       self.input_drop(mouse_down.x, mouse_down.y, mouse.column, mouse.row);
                     }
                   }
+                  event::MouseButton::Right => {
+                    self.mouse_right_clicks.push(Position { x: mouse.column, y: mouse.row });
+                  }
                   _ => ()
                 }
               },
@@ -589,21 +1192,86 @@ impl App {
               }
             }
           }
-          SimOutput::ShipState(_user, ship) => {
+          SimOutput::ShipState(user, ship) => {
             // debug!("Ship state: {:?}", ship);
-            // self.ship = ship;
-            self.ship.phy.pos.x = ship.phy.pos.x;
-            self.ship.phy.pos.y = ship.phy.pos.y;
-            self.ship.phy.heading = ship.phy.heading;
-            self.ship.flight.color = ship.flight.color;
+            self.map.record(user, (ship.phy.pos.x, ship.phy.pos.y));
+            self.ships.insert(user, ship);
 
             // ship_state_tx.send((ship, self.ui_regions.full)).unwrap();
           }
+          SimOutput::OtherShips(ships) => {
+            self.other_ships = ships;
+          }
+          SimOutput::BreakpointHit(user, addr, hits, message) => {
+            if let Some(bp) = self.breakpoints.iter_mut().find(|bp| bp.addr == addr) {
+              bp.hits = hits;
+            }
+            let text = match message {
+              Some(message) => format!("trace @{:04x}: {}", addr, message),
+              None => format!("Breakpoint @{:04x} hit ({})", addr, hits),
+            };
+            self.printc(vec![
+              (S!("U"), Color::White),
+              (format!("{}", user), Color::LightBlue),
+              (S!(": "), Color::White),
+              (text, Color::LightYellow),
+            ]);
+          }
+          SimOutput::Coverage(user, coverage) => {
+            if self.sim_state.active_user == user {
+              self.coverage = coverage;
+            }
+          }
+          SimOutput::Profile(user, profile) => {
+            if self.sim_state.active_user == user {
+              self.profile = profile;
+            }
+          }
+          SimOutput::SnapshotSaved(path, users, skipped) => {
+            self.print_plain(format!("Saved {} to {}", describe_users(&users), path));
+            if skipped > 0 {
+              self.print_plain(format!("Skipped {} scheduled processes without a known user ID", skipped));
+            }
+          }
+          SimOutput::SnapshotLoaded(path, users) => {
+            self.print_plain(format!("Restored {} from {}", describe_users(&users), path));
+          }
+          SimOutput::Users(users) => {
+            self.ships.retain(|user, _| users.iter().any(|status| status.user == Some(*user)));
+            self.map.retain(|user| users.iter().any(|status| status.user == Some(user)));
+            self.users = users;
+            self.users_selected = self.users_selected.min(self.users.len().saturating_sub(1));
+          }
+          SimOutput::TraceClosed(path, records) => {
+            self.print_plain(format!("Wrote {} trace records to {}", records, path));
+          }
+          SimOutput::History(user, len, rewound) => {
+            if self.sim_state.active_user == user {
+              self.history = (len, rewound);
+            }
+          }
+          SimOutput::WatchpointHit(user, hit) => {
+            self.printc(vec![
+              (S!("U"), Color::White),
+              (format!("{}", user), Color::LightBlue),
+              (S!(": "), Color::White),
+              (hit.to_string(), Color::LightMagenta),
+            ]);
+          }
+          SimOutput::Stopped(user, reason, ticks) => {
+            self.print_plain(format!("User {} stopped after {} ticks: {}", user, ticks, reason));
+          }
         }
       }
     }
   }
 
+  /// Resolves a symbol name, falling back to a hex address.
+  fn parse_addr(&self, text: &str) -> Option<u16> {
+    let hex = text.strip_prefix("0x").unwrap_or(text);
+    self.symbols.lookup(text).or_else(|| u16::from_str_radix(hex, 16).ok())
+  }
+
   fn print<'a>(&'a mut self, text: Vec<ColoredString>) {
     self.log_strings.push(text);
     if self.log_strings.len() > 200 {
@@ -649,6 +1317,21 @@ impl App {
       ViewMode::Code => {
         self.draw_code_view(frame, self.ui_regions.main);
       }
+      ViewMode::Profile => {
+        self.draw_profile_view(frame, self.ui_regions.main);
+      }
+      ViewMode::Users => {
+        self.draw_users_view(frame, self.ui_regions.main);
+      }
+      ViewMode::Modules => {
+        self.draw_modules_view(frame, self.ui_regions.main);
+      }
+      ViewMode::Map => {
+        self.draw_map_view(frame, self.ui_regions.main);
+      }
+      ViewMode::Instruments => {
+        self.draw_instruments_view(frame, self.ui_regions.main);
+      }
     }
 
     self.draw_status_box(frame);
@@ -665,41 +1348,92 @@ impl App {
       }
     }
 
-    self.draw_ship(frame);
+    if self.view_mode != ViewMode::Map {
+      self.draw_ships(frame);
+    }
 
     self.mouse_clicks.clear();
+    self.mouse_right_clicks.clear();
   }
 
-  fn draw_ship(&mut self, frame: &mut Frame) {
-    // debug!("Ship: {:?}", self.ship);
-    let ship = &self.ship;
-
-    let h = (ship.phy.heading * 8f32 + (1f32/16f32)).floor() as u8;
-    let h = h % 8;
-    let triangle = match h {
-      0 => "⇑",
-      1 => "⇗",
-      2 => "⇒",
-      3 => "⇘",
-      4 => "⇓",
-      5 => "⇙",
-      6 => "⇐",
-      7 => "⇖",
-      _ => "⇑",
-    };
+  /// Where the active user's nav module is pointing: the selected target's
+  /// ship if we know it, otherwise the target's absolute position.
+  fn nav_target(&self) -> Option<(f32, f32)> {
+    let memory = &self.sim_state.memory;
+    let nav = Module::installed(&self.module_defs, memory).into_iter().flatten().find_map(|m| m.nav_state(&self.module_defs, memory))?;
+    if nav.target_selector == 0 {
+      return None;
+    }
+    match self.ships.get(&nav.target_id) {
+      Some(ship) => Some((ship.phy.pos.x, ship.phy.pos.y)),
+      None => Some((nav.target_screen.0 as f32, nav.target_screen.1 as f32)),
+    }
+  }
+
+  /// Every ship, with its user ID where it is known.
+  fn all_ships(&self) -> impl Iterator<Item = (Option<u64>, &Ship)> {
+    let known = self.ships.iter().map(|(&user, ship)| (Some(user), ship));
+    known.chain(self.other_ships.iter().map(|ship| (None, ship)))
+  }
+
+  fn draw_ships(&mut self, frame: &mut Frame) {
     let w = frame.area().width as f32;
     let h = frame.area().height as f32;
-    let x = (ship.phy.pos.x / 1920f32) * (w - 1f32);
-    let y = (ship.phy.pos.y / 1080f32) * (h - 1f32);
-    let color = ship.flight.color;
-    let color = color_from_value(color);
-
-    let x = (x as u16).clamp(0, w as u16 - 1);
-    let y = (y as u16).clamp(0, h as u16 - 1);
+    let to_screen = |x: f32, y: f32| {
+      let x = (x / 1920f32) * (w - 1f32);
+      let y = (y / 1080f32) * (h - 1f32);
+      ((x.max(0.0) as u16).min(w as u16 - 1), (y.max(0.0) as u16).min(h as u16 - 1))
+    };
 
-    // debug!("Ship: {:?} {} {} {} {}", ship.x, ship.y, x, y, flight.current_compass);
+    let active = self.sim_state.active_user;
+    if let Some(ship) = self.ships.get(&active) && let Some((tx, ty)) = self.nav_target() {
+      let (x0, y0) = to_screen(ship.phy.pos.x, ship.phy.pos.y);
+      let (x1, y1) = to_screen(tx, ty);
+      let steps = (x1 as i32 - x0 as i32).abs().max((y1 as i32 - y0 as i32).abs());
+      let style = Style::default().fg(color_from_value(ship.flight.color)).add_modifier(Modifier::DIM);
+      for i in 1..steps {
+        let t = i as f32 / steps as f32;
+        let x = (x0 as f32 + (x1 as f32 - x0 as f32) * t).round() as u16;
+        let y = (y0 as f32 + (y1 as f32 - y0 as f32) * t).round() as u16;
+        frame.buffer_mut().set_string(x, y, "·", style);
+      }
+      if steps > 0 {
+        frame.buffer_mut().set_string(x1, y1, "×", style);
+      }
+    }
 
-    frame.buffer_mut().set_string(x, y, triangle, Style::default().fg(color));
+    for (user, ship) in self.all_ships() {
+      let h = (ship.phy.heading * 8f32 + (1f32/16f32)).floor() as u8;
+      let h = h % 8;
+      let triangle = match h {
+        0 => "⇑",
+        1 => "⇗",
+        2 => "⇒",
+        3 => "⇘",
+        4 => "⇓",
+        5 => "⇙",
+        6 => "⇐",
+        7 => "⇖",
+        _ => "⇑",
+      };
+      let (x, y) = to_screen(ship.phy.pos.x, ship.phy.pos.y);
+      let color = color_from_value(ship.flight.color);
+
+      let mut style = Style::default().fg(color);
+      let mut label_style = Style::default().fg(Color::DarkGray);
+      if user == Some(active) {
+        style = style.bg(Color::Rgb(48, 48, 64)).add_modifier(Modifier::BOLD);
+        label_style = Style::default().fg(Color::LightCyan);
+      }
+      frame.buffer_mut().set_string(x, y, triangle, style);
+      let Some(user) = user else {
+        continue;
+      };
+      let label = format!("U{}", user);
+      if x + 1 + label.len() as u16 <= w as u16 {
+        frame.buffer_mut().set_string(x + 1, y, label, label_style);
+      }
+    }
 
     // if self.ship_image.is_some() {
     //   let mut image = self.ship_image.as_mut().unwrap();
@@ -720,6 +1454,8 @@ impl App {
     // let mut prev_was_pcinc = false;
     // let mut prev_dst = 0;
     let mut loading = 0;
+    let hottest = self.profile.hottest();
+    let instructions = coverage::instructions(&self.sim_state.memory);
     for (i, &val) in m.enumerate() {
       let mut spans = Vec::new();
       let addr = self.code_offset as u16 + i as u16;
@@ -743,8 +1479,18 @@ impl App {
         }
       }
 
-      if self.breakpoints.contains(&addr) {
-        spans.push("●".to_string().red());
+      // Right click runs to the line.
+      if mouse_over && let Some(click) = self.mouse_right_clicks.last()
+        && click.y == (i.saturating_sub(self.code_scroll)) as u16 + rect.y {
+        self.actions.push(AppActions::RunToCursor(addr));
+        self.mouse_right_clicks.pop();
+      }
+
+      if let Some(bp) = self.breakpoints.iter().find(|bp| bp.addr == addr) {
+        match bp.kind {
+          BreakpointKind::Trace(..) => spans.push(bp.glyph().to_string().light_blue()),
+          BreakpointKind::Break => spans.push(bp.glyph().to_string().red()),
+        }
       } else {
         if mouse_over {
           spans.push("●".to_string().dark_gray());
@@ -752,7 +1498,19 @@ impl App {
           spans.push(" ".to_string().white());
         }
       }
-      if i + self.code_offset == pc {
+      // Heat gutter from the profiler, falling back to coverage from earlier runs.
+      match (self.profile.at(addr), self.coverage.hits_at(addr)) {
+        (Some(spot), _) if spot.executed > 0 => {
+          spans.push("▌".fg(heat_color(spot.executed as f32 / hottest as f32)));
+        }
+        (_, Some(hits)) if hits > 0 => spans.push("▌".dark_gray()),
+        (_, Some(0)) if self.coverage.enabled && instructions.contains(&addr) => spans.push("·".red()),
+        _ => spans.push(" ".white()),
+      }
+      if i + self.code_offset == pc && self.history.1 > 0 {
+        // Rewound into the recorded history.
+        spans.push("<".to_string().yellow());
+      } else if i + self.code_offset == pc {
         spans.push(">".to_string().green());
       } else {
         spans.push(" ".to_string().white());
@@ -804,6 +1562,10 @@ impl App {
         _ => format!("{:04x}", src_val1),
       };
 
+      if let Some(label) = self.symbols.label_at(addr) {
+        spans.push(format!("{}: ", label).light_yellow());
+      }
+
       if load_literal {
         spans.push(opcode.to_string().gray());
         spans.push(" <- ".green());
@@ -816,6 +1578,10 @@ impl App {
         }
       }
 
+      if let Some((line, text)) = self.symbols.source_at(addr) {
+        spans.push(format!("  ; {}: {}", line, text).dark_gray());
+      }
+
       lines.push(Line::from(spans));
     }
 
@@ -830,12 +1596,599 @@ impl App {
 
     frame.render_widget(Paragraph::new(lines.to_vec()), rect);
 
+    // Position in the recorded timeline.
+    if self.history.0 > 0 || self.history.1 > 0 {
+      let total = self.history.0 + self.history.1;
+      let timeline = Line::from(vec![
+        "history ".dark_gray(),
+        format!("-{}", self.history.1).fg(if self.history.1 > 0 { Color::Yellow } else { Color::DarkGray }),
+        format!("/{} ", total).dark_gray(),
+      ]).right_aligned();
+      frame.render_widget(timeline, Rect::new(rect.x, rect.y, rect.width.saturating_sub(1), 1));
+    }
+
     let max = 0x60;
     let scroll_start = self.code_scroll as i32 - self.code_offset as i32;
     let scroll_end = scroll_start + rect.height as i32 - 1 - self.code_offset as i32;
     self.draw_scrollbar(frame, true, max as i16, scroll_start as i16, scroll_end as i16);
   }
 
+  fn draw_profile_view(&mut self, frame: &mut Frame, rect: Rect) {
+    let total = (self.profile.executed + self.profile.sleeping).max(1);
+    let percent = |ticks: u64| format!("{:5.1}%", ticks as f32 * 100.0 / total as f32);
+
+    let mut spots = self.profile.spots.clone();
+    spots.sort_by_key(|spot| std::cmp::Reverse(spot.executed + spot.sleeping));
+
+    let mut summary = vec![
+      format!("{} ticks: {} executed, {} sleeping", total, self.profile.executed, self.profile.sleeping).white(),
+    ];
+    if !self.profile.enabled {
+      summary.push("  (profiler off, `profile on` to record)".dark_gray());
+    }
+    let mut lines = vec![Line::from(summary), Line::from(vec![
+      format!("{:<4}  {:>10} {:>6}  {:>10} {:>6}  {}", "addr", "executed", "", "sleeping", "", "instruction").dark_gray(),
+    ])];
+    let hottest = self.profile.hottest();
+    for spot in spots.iter().skip(self.profile_scroll).take(rect.height.saturating_sub(2) as usize) {
+      let opcode = Opcode::parse(self.sim_state.memory[spot.addr as usize % MEM_SHARED_SIZE_U]);
+      let mut spans = vec![
+        "▌".fg(heat_color(spot.executed as f32 / hottest.max(1) as f32)),
+        format!("{:04x} ", spot.addr).white(),
+        format!("{:>10} ", spot.executed).light_green(),
+        percent(spot.executed).green(),
+        format!("  {:>10} ", spot.sleeping).light_blue(),
+        percent(spot.sleeping).blue(),
+        "  ".white(),
+      ];
+      if let Some(label) = self.symbols.label_at(spot.addr) {
+        spans.push(format!("{}: ", label).light_yellow());
+      }
+      spans.push(opcode.to_string().white());
+      lines.push(Line::from(spans));
+    }
+    let kinds_width = 24;
+    let table = Rect::new(rect.x, rect.y, rect.width.saturating_sub(kinds_width), rect.height);
+    frame.render_widget(Paragraph::new(lines), table);
+
+    let mut lines = vec![Line::from("By opcode".dark_gray())];
+    for (kind, count) in self.profile.kinds.iter().take(rect.height.saturating_sub(1) as usize) {
+      lines.push(Line::from(vec![
+        format!("{:<12}", kind).white(),
+        format!("{:>10}", count).light_green(),
+      ]));
+    }
+    let kinds = Rect::new(rect.right().saturating_sub(kinds_width), rect.y, kinds_width.min(rect.width), rect.height);
+    frame.render_widget(Paragraph::new(lines), kinds);
+  }
+
+  fn draw_users_view(&mut self, frame: &mut Frame, rect: Rect) {
+    let mut lines = vec![Line::from(vec![
+      format!(" {:<7} {:<10} {:<4}  {:<28} {:>6}  {:>14}", "user", "state", "pc", "instruction", "sleep", "position").dark_gray(),
+    ])];
+    for (i, status) in self.users.iter().enumerate().take(rect.height.saturating_sub(2) as usize) {
+      let y = rect.y + 1 + i as u16;
+      if let Some(click) = self.mouse_clicks.last()
+        && click.y == y && rect.contains(*click) {
+        self.users_selected = i;
+        if let Some(user) = status.user {
+          self.actions.push(AppActions::SetUser(user));
+        }
+        self.mouse_clicks.pop();
+      }
+
+      let (state, color) = match status.state {
+        UserState::Running => ("running", Color::Green),
+        UserState::Halted => ("halted", Color::Red),
+        UserState::Debug => ("debug", Color::Yellow),
+        UserState::Breakpoint => ("breakpoint", Color::LightMagenta),
+      };
+      let active = status.user == Some(self.sim_state.active_user);
+      let (user, pc, instruction) = match status.user {
+        Some(user) => (format!("U{:<6} ", user), format!("{:04x}  ", status.pc), Opcode::parse(status.word).to_string()),
+        None => (format!("{:<8}", "?"), format!("{:<6}", "-"), S!("-")),
+      };
+      let mut line = Line::from(vec![
+        (if active { "▶" } else { " " }).light_cyan(),
+        user.fg(if status.scheduled { Color::LightBlue } else { Color::DarkGray }),
+        format!("{:<10} ", state).fg(color),
+        pc.white(),
+        format!("{:<28} ", instruction).white(),
+        format!("{:>6}  ", status.sleep).light_blue(),
+        format!("{:>6.0},{:<6.0}", status.pos.0, status.pos.1).white(),
+      ]);
+      if i == self.users_selected {
+        line = line.patch_style(Style::default().bg(Color::Rgb(32, 32, 48)));
+      }
+      lines.push(line);
+    }
+    frame.render_widget(Paragraph::new(lines), rect);
+  }
+
+  /// Shows a module slot's registers in the Memory view.
+  fn show_slot(&mut self, slot: usize) {
+    self.memory_scroll = 0x300 + slot * 0x20;
+    self.view_mode = ViewMode::Memory;
+  }
+
+  fn draw_modules_view(&mut self, frame: &mut Frame, rect: Rect) {
+    let memory = &self.sim_state.memory;
+    let defs = &self.module_defs;
+    let modules = Module::installed(defs, memory);
+    let mut lines = vec![Line::from(vec![
+      format!(" {:<4} {:<4}  {:<4}  {:<6} {:<12} {:<4}", "slot", "base", "id", "select", "module", "status").dark_gray(),
+    ])];
+    let mut clicked = None;
+    for (slot, module) in modules.iter().enumerate() {
+      let y = rect.y + 1 + slot as u16;
+      if let Some(click) = self.mouse_clicks.last()
+        && click.y == y && rect.contains(*click) {
+        clicked = Some(slot);
+        self.mouse_clicks.pop();
+      }
+
+      let id = memory[0x318 + slot];
+      let base = 0x300 + slot * 0x20;
+      // The slot's module select register in the control block, e.g. CMS3.
+      let select = Module::Control(0).register(defs, 0x318 + slot).map(|reg| reg.name.as_str()).unwrap_or("");
+      let name = module.and_then(|m| m.def(defs)).map(|def| def.name.as_str()).unwrap_or("?");
+      let mut line = Line::from(vec![
+        format!(" {:<4} ", slot).white(),
+        format!("{:04x}  ", base).dark_gray(),
+        format!("{:04x}  ", id).fg(color_from_value(id)),
+        format!("{:<6} ", select).gray(),
+        format!("{:<12} ", name).fg(if module.is_some() { Color::LightCyan } else { Color::Red }),
+        format!("{:04x}", memory[base]).white(),
+      ]);
+      if let Some((_, _, kind)) = module.and_then(|m| m.module_register_info(defs, base))
+        && let Some(value) = kind.decode(memory, base) {
+        line.push_span(" = ".dark_gray());
+        line.push_span(value);
+      }
+      match module {
+        None => line.push_span(format!("  unknown module ID {:04x}", id).red()),
+        Some(_) if slot > 0 && id == 0 => line.push_span("  no module selected".yellow()),
+        Some(_) => (),
+      }
+      if slot == self.modules_selected {
+        line = line.patch_style(Style::default().bg(Color::Rgb(32, 32, 48)));
+      }
+      lines.push(line);
+    }
+    frame.render_widget(Paragraph::new(lines), rect);
+
+    if let Some(slot) = clicked {
+      self.modules_selected = slot;
+      self.show_slot(slot);
+    }
+  }
+
+  fn draw_map_view(&mut self, frame: &mut Frame, rect: Rect) {
+    let block = Block::bordered()
+      .title_top(Line::from(vec![
+        "Map ".white(),
+        format!("{:.2}x ", self.map.zoom).light_cyan(),
+        "[+/-] ".light_blue(),
+        "zoom ".white(),
+        "[arrows] ".light_blue(),
+        "pan ".white(),
+        "[c] ".light_blue(),
+        "center".white(),
+      ]))
+      .border_style(Style::default().fg(Color::DarkGray))
+      .border_type(BorderType::Rounded);
+    let inner = block.inner(rect);
+    let (x_bounds, y_bounds) = self.map.bounds(inner.width, inner.height);
+    let spacing = self.map.grid_spacing() as f64;
+    let active = self.sim_state.active_user;
+    let target = self.nav_target();
+    let view = &self.map;
+    let ships = self.all_ships().collect::<Vec<_>>();
+
+    let canvas = Canvas::default()
+      .block(block)
+      .marker(Marker::Braille)
+      .x_bounds(x_bounds)
+      .y_bounds(y_bounds)
+      .paint(|ctx| {
+        let grid = Color::Rgb(40, 40, 40);
+        let mut x = (x_bounds[0] / spacing).ceil() * spacing;
+        while x <= x_bounds[1] {
+          ctx.draw(&canvas::Line::new(x, y_bounds[0], x, y_bounds[1], grid));
+          x += spacing;
+        }
+        let mut y = (y_bounds[0] / spacing).ceil() * spacing;
+        while y <= y_bounds[1] {
+          ctx.draw(&canvas::Line::new(x_bounds[0], y, x_bounds[1], y, grid));
+          y += spacing;
+        }
+        ctx.draw(&canvas::Rectangle {
+          x: 0.0,
+          y: -map::WORLD_HEIGHT as f64,
+          width: map::WORLD_WIDTH as f64,
+          height: map::WORLD_HEIGHT as f64,
+          color: Color::DarkGray,
+        });
+        ctx.layer();
+
+        for &(user, ship) in ships.iter() {
+          let color = color_from_value(ship.flight.color);
+          let pos = (ship.phy.pos.x, ship.phy.pos.y);
+
+          // Older positions fade out in four steps. Only known users have a trail.
+          let trail = user.into_iter().flat_map(|user| view.trail(user)).map(|&p| map::canvas_point(p)).collect::<Vec<_>>();
+          for (i, chunk) in trail.chunks(trail.len().div_ceil(4).max(1)).enumerate() {
+            let fade = 0.8 - 0.2 * i as f32;
+            ctx.draw(&Points { coords: chunk, color: fade_color(color, fade) });
+          }
+
+          let (x, y) = map::canvas_point(pos);
+          let end = map::predict(pos, (ship.phy.vel.x, ship.phy.vel.y), view.predict_ticks);
+          let (px, py) = map::canvas_point(end);
+          ctx.draw(&canvas::Line::new(x, y, px, py, fade_color(color, 0.5)));
+
+          if user == Some(active) && let Some(target) = target {
+            let (tx, ty) = map::canvas_point(target);
+            ctx.draw(&canvas::Line::new(x, y, tx, ty, Color::Rgb(96, 96, 160)));
+          }
+
+          // Heading is in turns, clockwise from up.
+          let angle = ship.phy.heading as f64 * std::f64::consts::TAU;
+          let len = spacing / 4.0;
+          ctx.draw(&canvas::Line::new(x, y, x + angle.sin() * len, y + angle.cos() * len, color));
+
+          let Some(user) = user else {
+            continue;
+          };
+          let label = format!("U{}", user);
+          if user == active {
+            ctx.print(x, y, label.light_cyan().bold());
+          } else {
+            ctx.print(x, y, label.fg(color));
+          }
+        }
+      });
+    frame.render_widget(canvas, rect);
+  }
+
+  /// Panels for the active user's ship modules.
+  fn draw_instruments_view(&mut self, frame: &mut Frame, rect: Rect) {
+    let half = rect.width / 2;
+    let top = rect.height * 3 / 5;
+    self.draw_radar_panel(frame, Rect::new(rect.x, rect.y, half, top));
+    self.draw_nav_panel(frame, Rect::new(rect.x + half, rect.y, rect.width - half, top));
+    self.draw_flight_panel(frame, Rect::new(rect.x, rect.y + top, rect.width, rect.height - top));
+  }
+
+  /// Queues a write to the sim and updates the local copy right away, so
+  /// adjustments made before the next memory update add up.
+  fn write_register(&mut self, addr: u16, val: u16) {
+    self.sim_state.memory[addr as usize] = val;
+    self.actions.push(AppActions::Write(addr, val));
+  }
+
+  /// Writes the register called `name` in `module`, if its definition has one.
+  fn write_named(&mut self, module: Module, name: &str, val: u16) {
+    if let Some(addr) = module.register_addr(&self.module_defs, name) {
+      self.write_register(addr, val);
+    }
+  }
+
+  fn flight_module(&self) -> Option<(Module, FlightState)> {
+    let memory = &self.sim_state.memory;
+    Module::installed(&self.module_defs, memory).into_iter().flatten().find_map(|m| Some((m, m.flight_state(&self.module_defs, memory)?)))
+  }
+
+  /// Turns the requested heading by `turn` turns and changes the requested
+  /// velocity by `dv`, as register writes.
+  fn steer(&mut self, turn: f32, dv: (f32, f32)) {
+    let Some((module, flight)) = self.flight_module() else {
+      return;
+    };
+    if turn != 0.0 {
+      self.write_named(module, FlightState::RH, to_angle(flight.requested_heading + turn));
+    }
+    if dv.0 != 0.0 {
+      self.write_named(module, FlightState::RRVX, to_fixed(flight.requested_vel.0 + dv.0));
+    }
+    if dv.1 != 0.0 {
+      self.write_named(module, FlightState::RRVY, to_fixed(flight.requested_vel.1 + dv.1));
+    }
+  }
+
+  fn draw_flight_panel(&mut self, frame: &mut Frame, rect: Rect) {
+    let Some((module, flight)) = self.flight_module() else {
+      frame.render_widget(Paragraph::new("No flight module installed".dark_gray()).block(Block::bordered()
+        .title_top("Flight")
+        .border_style(Style::default().fg(Color::DarkGray))
+        .border_type(BorderType::Rounded)), rect);
+      return;
+    };
+    let base = module.base();
+
+    let compass_width = (rect.height * 2 + 2).min(rect.width / 2);
+    let compass = Rect::new(rect.x, rect.y, compass_width, rect.height);
+    let side = Rect::new(compass.right(), rect.y, rect.width - compass_width, rect.height);
+
+    let fastest = [flight.requested_vel.0, flight.requested_vel.1, flight.current_vel.0, flight.current_vel.1]
+      .into_iter()
+      .fold(0f32, |max, v| max.max(v.abs()));
+    let range = (fastest.ceil() as u32).max(1).next_power_of_two() as f64;
+    let block = Block::bordered()
+      .title_top(Line::from(vec![
+        format!("Flight @{:04x} ", base).white(),
+        format!("range {}", range).dark_gray(),
+      ]))
+      .border_style(Style::default().fg(Color::DarkGray))
+      .border_type(BorderType::Rounded);
+    let inner = block.inner(compass);
+    let aspect = inner.width as f64 / (inner.height.max(1) as f64 * 2.0);
+    let (x_bounds, y_bounds) = ([-aspect * 1.1, aspect * 1.1], [-1.1, 1.1]);
+
+    // Click sets the requested heading, right click the requested velocity.
+    let to_canvas = |pos: Position| {
+      let fx = (pos.x - inner.x) as f64 + 0.5;
+      let fy = (pos.y - inner.y) as f64 + 0.5;
+      (
+        x_bounds[0] + fx / inner.width as f64 * (x_bounds[1] - x_bounds[0]),
+        y_bounds[1] - fy / inner.height as f64 * (y_bounds[1] - y_bounds[0]),
+      )
+    };
+    if let Some(&click) = self.mouse_clicks.last() && inner.contains(click) {
+      let (x, y) = to_canvas(click);
+      let turns = x.atan2(y) / std::f64::consts::TAU;
+      self.write_named(module, FlightState::RH, to_angle(turns as f32));
+      self.mouse_clicks.pop();
+    }
+    if let Some(&click) = self.mouse_right_clicks.last() && inner.contains(click) {
+      let (x, y) = to_canvas(click);
+      // Screen y points down.
+      self.write_named(module, FlightState::RRVX, to_fixed((x * range) as f32));
+      self.write_named(module, FlightState::RRVY, to_fixed((-y * range) as f32));
+      self.mouse_right_clicks.pop();
+    }
+
+    let point = |turns: f32, r: f64| {
+      let angle = turns as f64 * std::f64::consts::TAU;
+      (angle.sin() * r, angle.cos() * r)
+    };
+    let requested = Color::LightYellow;
+    let current = color_from_value(flight.color);
+    let canvas = Canvas::default()
+      .block(block)
+      .marker(Marker::Braille)
+      .x_bounds(x_bounds)
+      .y_bounds(y_bounds)
+      .paint(|ctx| {
+        ctx.draw(&canvas::Circle { x: 0.0, y: 0.0, radius: 1.0, color: Color::DarkGray });
+        ctx.draw(&canvas::Circle { x: 0.0, y: 0.0, radius: 0.5, color: Color::Rgb(40, 40, 40) });
+        let (x, y) = point(flight.requested_heading, 1.0);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, requested));
+        let (x, y) = point(flight.heading, 0.8);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, current));
+        ctx.layer();
+        let (rx, ry) = (flight.requested_vel.0 as f64 / range, -flight.requested_vel.1 as f64 / range);
+        ctx.print(rx, ry, "+".fg(requested));
+        let (cx, cy) = (flight.current_vel.0 as f64 / range, -flight.current_vel.1 as f64 / range);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, cx, cy, Color::LightCyan));
+      });
+    frame.render_widget(canvas, compass);
+
+    let degrees = |turns: f32| format!("{:7.1}°", turns * 360.0);
+    let mut lines = vec![
+      Line::from(format!("{:<9}{:>10}{:>10}", "", "requested", "current").dark_gray()),
+      Line::from(vec![
+        format!("{:<9}", "Heading").dark_gray(),
+        format!("{:>10}", degrees(flight.requested_heading)).fg(requested),
+        format!("{:>10}", degrees(flight.heading)).fg(current),
+      ]),
+      Line::from(vec![
+        format!("{:<9}", "Vx").dark_gray(),
+        format!("{:>10.2}", flight.requested_vel.0).fg(requested),
+        format!("{:>10.2}", flight.current_vel.0).light_cyan(),
+      ]),
+      Line::from(vec![
+        format!("{:<9}", "Vy").dark_gray(),
+        format!("{:>10.2}", flight.requested_vel.1).fg(requested),
+        format!("{:>10.2}", flight.current_vel.1).light_cyan(),
+      ]),
+      Line::from(""),
+    ];
+    // Set EEN bits by the names in the register definition.
+    let mut bits = vec![format!("{:<5}{:04x} ", "EEN", flight.engine_flags).dark_gray()];
+    if let Some(een) = module.register_addr(&self.module_defs, FlightState::EEN).map(usize::from)
+      && let Some(value) = module.register(&self.module_defs, een).and_then(|reg| reg.value.decode(&self.sim_state.memory, een)) {
+      bits.push(value);
+    }
+    lines.push(Line::from(bits));
+    lines.push(Line::from(vec![
+      format!("{:<5}{:04x} ", "SHCC", flight.color).dark_gray(),
+      "███".fg(color_from_value(flight.color)),
+      format!("  SHCM {:04x}", flight.alpha_mode).dark_gray(),
+    ]));
+    lines.push(Line::from(""));
+    lines.push(Line::from(vec![
+      "[arrows] ".light_blue(), "RRV  ".white(),
+      "[ ] ".light_blue(), "RH  ".white(),
+      "[0] ".light_blue(), "stop  ".white(),
+      "click ".light_blue(), "RH  ".white(),
+      "right click ".light_blue(), "RRV".white(),
+    ]));
+    frame.render_widget(Paragraph::new(lines).block(Block::new().padding(Padding::horizontal(1))), side);
+  }
+
+  fn draw_nav_panel(&mut self, frame: &mut Frame, rect: Rect) {
+    let memory = &self.sim_state.memory;
+    let nav = Module::installed(&self.module_defs, memory).into_iter().flatten().find_map(|m| Some((m, m.nav_state(&self.module_defs, memory)?)));
+    let Some((module, nav)) = nav else {
+      frame.render_widget(Paragraph::new("No nav module installed".dark_gray()).block(Block::bordered()
+        .title_top("Nav")
+        .border_style(Style::default().fg(Color::DarkGray))
+        .border_type(BorderType::Rounded)), rect);
+      return;
+    };
+
+    let legend_width = 30.min(rect.width / 2);
+    let compass = Rect::new(rect.x, rect.y, rect.width - legend_width, rect.height);
+    let legend = Rect::new(compass.right(), rect.y, legend_width, rect.height);
+
+    let (dx, dy) = nav.target_rel;
+    let range = (dx.unsigned_abs().max(dy.unsigned_abs()).max(64) as u32).next_power_of_two() as f64;
+    let block = Block::bordered()
+      .title_top(Line::from(vec![
+        format!("Nav @{:04x} ", module.base()).white(),
+        format!("range {}", range).dark_gray(),
+      ]))
+      .border_style(Style::default().fg(Color::DarkGray))
+      .border_type(BorderType::Rounded);
+    let inner = block.inner(compass);
+    let aspect = inner.width as f64 / (inner.height.max(1) as f64 * 2.0);
+    let point = |turns: f32, r: f64| {
+      let angle = turns as f64 * std::f64::consts::TAU;
+      (angle.sin() * r, angle.cos() * r)
+    };
+    let ship = self.ships.get(&self.sim_state.active_user);
+    let ship_heading = ship.map(|ship| ship.phy.heading).unwrap_or(0.0);
+    let ship_color = ship.map(|ship| color_from_value(ship.flight.color)).unwrap_or(Color::White);
+    let predict_ticks = self.map.predict_ticks as f64;
+
+    let canvas = Canvas::default()
+      .block(block)
+      .marker(Marker::Braille)
+      .x_bounds([-aspect * 1.25, aspect * 1.25])
+      .y_bounds([-1.25, 1.25])
+      .paint(|ctx| {
+        ctx.draw(&canvas::Circle { x: 0.0, y: 0.0, radius: 1.0, color: Color::DarkGray });
+        for (turns, label) in [(0.0, "N"), (0.25, "E"), (0.5, "S"), (0.75, "W")] {
+          let (x, y) = point(turns, 1.15);
+          ctx.print(x, y, label.dark_gray());
+        }
+        // Absolute headings as arrows, relative ones as ticks off the ship's heading.
+        let (x, y) = point(nav.heading_toward, 1.0);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, Color::LightGreen));
+        let (x, y) = point(nav.heading_away, 1.0);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, Color::Rgb(128, 48, 48)));
+        for (rel, color) in [(nav.rel_heading_toward, Color::LightGreen), (nav.rel_heading_away, Color::Rgb(128, 48, 48))] {
+          let (x1, y1) = point(ship_heading + rel, 1.0);
+          let (x2, y2) = point(ship_heading + rel, 1.1);
+          ctx.draw(&canvas::Line::new(x1, y1, x2, y2, color));
+        }
+        ctx.layer();
+
+        let (x, y) = point(ship_heading, 0.25);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, ship_color));
+        if nav.target_selector != 0 {
+          // Screen y points down.
+          let (tx, ty) = (dx as f64 / range, -dy as f64 / range);
+          let (vx, vy) = (nav.target_rel_vel.0 as f64, -nav.target_rel_vel.1 as f64);
+          let (ex, ey) = (tx + vx * predict_ticks / range, ty + vy * predict_ticks / range);
+          ctx.draw(&canvas::Line::new(tx, ty, ex, ey, Color::LightMagenta));
+          ctx.print(tx, ty, "◎".light_yellow());
+        }
+      });
+    frame.render_widget(canvas, compass);
+
+    let regs = &memory[module.base() as usize..module.base() as usize + 0x20];
+    let row = |name: &'static str, offset: usize, value: String| Line::from(vec![
+      format!("{:<5}", name).dark_gray(),
+      format!("{:04x} ", regs[offset]).fg(Color::Rgb(96, 96, 96)),
+      value.white(),
+    ]);
+    let degrees = |turns: f32| format!("{:7.1}°", turns * 360.0);
+    let lines = vec![
+      row("NASx", 0x04, format!("{:7}", nav.screen.0)),
+      row("NASy", 0x05, format!("{:7}", nav.screen.1)),
+      row("NTSx", 0x08, format!("{:7}", nav.target_screen.0)),
+      row("NTSy", 0x09, format!("{:7}", nav.target_screen.1)),
+      row("NTGT", 0x0c, format!("{:7}", nav.target_selector)),
+      Line::from(vec!["NTGI ".dark_gray(), format!("{:012x}", nav.target_id).light_yellow()]),
+      row("NRDx", 0x10, format!("{:7}", nav.target_rel.0)),
+      row("NRDy", 0x11, format!("{:7}", nav.target_rel.1)),
+      row("NRVx", 0x14, format!("{:7.2}", nav.target_rel_vel.0)),
+      row("NRVy", 0x15, format!("{:7.2}", nav.target_rel_vel.1)),
+      row("NAHT", 0x18, degrees(nav.heading_toward)),
+      row("NAHF", 0x1a, degrees(nav.heading_away)),
+      row("NRHT", 0x1c, degrees(nav.rel_heading_toward)),
+      row("NRHF", 0x1e, degrees(nav.rel_heading_away)),
+    ];
+    frame.render_widget(Paragraph::new(lines).block(Block::new().padding(Padding::horizontal(1))), legend);
+  }
+
+  fn draw_radar_panel(&mut self, frame: &mut Frame, rect: Rect) {
+    let memory = &self.sim_state.memory;
+    let radar = Module::installed(&self.module_defs, memory).into_iter().flatten().find_map(|m| Some((m, m.radar_state(&self.module_defs, memory)?)));
+    let Some((module, radar)) = radar else {
+      frame.render_widget(Paragraph::new("No radar module installed".dark_gray()).block(Block::bordered()
+        .title_top("Radar")
+        .border_style(Style::default().fg(Color::DarkGray))
+        .border_type(BorderType::Rounded)), rect);
+      return;
+    };
+
+    let legend_width = 30.min(rect.width / 2);
+    let scope = Rect::new(rect.x, rect.y, rect.width - legend_width, rect.height);
+    let legend = Rect::new(scope.right(), rect.y, legend_width, rect.height);
+
+    // Round the range up to a power of two so the rings don't jump around.
+    let farthest = radar.contacts.iter().map(|c| c.distance).max().unwrap_or(0);
+    let range = farthest.max(256).next_power_of_two() as f64;
+    let block = Block::bordered()
+      .title_top(Line::from(vec![
+        format!("Radar @{:04x} ", module.base()).white(),
+        format!("range {}", range).dark_gray(),
+      ]))
+      .border_style(Style::default().fg(Color::DarkGray))
+      .border_type(BorderType::Rounded);
+    let inner = block.inner(scope);
+    let aspect = inner.width as f64 / (inner.height.max(1) as f64 * 2.0);
+    let point = |turns: f32, r: f64| {
+      let angle = turns as f64 * std::f64::consts::TAU;
+      (angle.sin() * r, angle.cos() * r)
+    };
+
+    let canvas = Canvas::default()
+      .block(block)
+      .marker(Marker::Braille)
+      .x_bounds([-aspect * 1.05, aspect * 1.05])
+      .y_bounds([-1.05, 1.05])
+      .paint(|ctx| {
+        for ring in 1..=4 {
+          ctx.draw(&canvas::Circle { x: 0.0, y: 0.0, radius: ring as f64 / 4.0, color: Color::Rgb(32, 48, 32) });
+        }
+        let (x, y) = point(angle_turns(radar.last_heading), 1.0);
+        ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, Color::Rgb(64, 64, 64)));
+        // The scan cone, a few lines either side of the selected heading.
+        for step in -4..=4 {
+          let (x, y) = point(angle_turns(radar.scan_heading) + step as f32 / 256.0, 1.0);
+          let color = if step == 0 { Color::Rgb(80, 220, 80) } else { Color::Rgb(24, 96, 24) };
+          ctx.draw(&canvas::Line::new(0.0, 0.0, x, y, color));
+        }
+        ctx.layer();
+        // Contacts are from the last scan, so they sit on its heading.
+        for contact in radar.contacts.iter() {
+          let (x, y) = point(angle_turns(radar.last_heading), contact.distance as f64 / range);
+          ctx.print(x, y, "◆".fg(contact.color));
+        }
+      });
+    frame.render_widget(canvas, scope);
+
+    let degrees = |value: u16| format!("{:6.1}°", angle_turns(value) * 360.0);
+    let mut lines = vec![
+      Line::from(vec!["RSSH ".dark_gray(), degrees(radar.scan_heading).white()]),
+      Line::from(vec!["RHLS ".dark_gray(), degrees(radar.last_heading).white()]),
+      Line::from(vec!["RNSR ".dark_gray(), format!("{:6}", radar.count).white()]),
+      Line::from(""),
+    ];
+    for contact in radar.contacts.iter() {
+      lines.push(Line::from(vec![
+        "▌".fg(contact.color),
+        format!("{:>6} ", contact.distance).white(),
+        format!("{:012x}", contact.id).fg(contact.color),
+      ]));
+    }
+    frame.render_widget(Paragraph::new(lines).block(Block::new().padding(Padding::horizontal(1))), legend);
+  }
+
   fn draw_memory_view(&mut self, frame: &mut Frame, rect: Rect) {
     let mut lines = Vec::new();
 
@@ -845,12 +2198,7 @@ impl App {
     let block_width = 8;
     let block_x = 4 + 4 + 2 + 2;
 
-    let mut modules: [Option<Module>; 8] = [None; 8];
-
-    for addr in 0x318..0x320 {
-      let val = self.sim_state.memory[addr];
-      modules[addr - 0x318] = Module::type_from_id(val, addr as u16 - 0x318);
-    }
+    let modules = Module::installed(&self.module_defs, &self.sim_state.memory);
 
     let mut loading: u8 = 0;
 
@@ -930,8 +2278,13 @@ impl App {
           }
         },
         _ => {
-          if let Some(m) = module && let Some((_, desc)) = m.module_register_info(addr) {
-            vec!(desc)
+          if let Some(m) = module && let Some((_, desc, kind)) = m.module_register_info(&self.module_defs, addr) {
+            let mut spans = vec!(desc);
+            if let Some(value) = kind.decode(&self.sim_state.memory, addr) {
+              spans.push(" = ".dark_gray());
+              spans.push(value);
+            }
+            spans
           } else {
             vec![format!("{}", opcode).fg(Color::Rgb(64, 64, 64))]
           }
@@ -944,7 +2297,13 @@ impl App {
         format!("{:04x}", self.sim_state.memory[addr]).fg(color_from_value(self.sim_state.memory[addr])),
         format!("{}", group_char).dark_gray(),
       ];
+      if let Some(label) = self.symbols.label_at(addr as u16) {
+        spans.push(format!("{}: ", label).light_yellow());
+      }
       for l in desc { spans.push(l); }
+      if let Some((line, text)) = self.symbols.source_at(addr as u16) {
+        spans.push(format!("  ; {}: {}", line, text).dark_gray());
+      }
       lines.push(Line::from(spans));
     }
 
@@ -972,8 +2331,8 @@ impl App {
 
       let addr_name = Module::addr_to_slot(addr as u16)
         .and_then(|slot| modules[slot])
-        .and_then(|m| m.module_register_info(addr))
-        .and_then(|(name, _)| Some(name));
+        .and_then(|m| m.module_register_info(&self.module_defs, addr))
+        .and_then(|(name, _, _)| Some(name));
 
       if let Some(name) = addr_name {
         let r = Rect::new(
@@ -1106,13 +2465,41 @@ impl App {
     for (addr, size, str) in self.watch_addr.clone() {
       let start = addr as usize;
       let end = start + (size * 4) as usize;
-      let bh = (((end as f32 - start as f32) / 4f32).ceil() as u16 / 2).max(1) + 2;
+      let groups = ((end as f32 - start as f32) / 4f32).ceil() as u16;
+      // Decoded values take the second column, leaving one group per row.
+      let rows = if self.watch_decodes(start, end) { groups } else { groups / 2 };
+      let bh = rows.max(1) + 2;
       let rect = Rect::new(x, y, w, bh);
       self.draw_watch_box(frame, rect, start, end, str);
       y += bh;
     }
   }
 
+  /// Decoded values of the module registers among the four words at `addr`.
+  fn watch_decoded(&self, addr: usize) -> Vec<Span<'static>> {
+    let memory = &self.sim_state.memory;
+    let modules = Module::installed(&self.module_defs, memory);
+    let mut spans = Vec::new();
+    for addr in addr..(addr + 4).min(memory.len()) {
+      let Some(module) = Module::addr_to_slot(addr as u16).and_then(|slot| modules[slot]) else {
+        continue;
+      };
+      let Some(reg) = module.register(&self.module_defs, addr) else {
+        continue;
+      };
+      if let Some(value) = reg.value.decode(memory, addr) {
+        spans.push(format!("{} ", reg.name.trim()).fg(reg.name_color));
+        spans.push(value);
+        spans.push("  ".into());
+      }
+    }
+    spans
+  }
+
+  fn watch_decodes(&self, start: usize, end: usize) -> bool {
+    (start..end).step_by(4).any(|addr| !self.watch_decoded(addr).is_empty())
+  }
+
   fn draw_watch_box(&mut self, frame: &mut Frame, rect: Rect, start: usize, end: usize, str: Option<String>) {
     let rect = rect.intersection(frame.area());
 
@@ -1124,17 +2511,45 @@ impl App {
     if let Some(str) = str {
       watch_block = watch_block.title_top(Line::from(str).light_yellow().centered());
     }
-    frame.render_widget(Clear, rect);
-    frame.render_widget(watch_block, rect);
 
     // let start = self.watch_addr as usize;
     let end = (end as f32 / 4f32).ceil() as usize * 4;
     let end = if end <= start { start + 4 } else { end };
+    let per_row = if self.watch_decodes(start, end) { 1 } else { 2 };
+
+    // Hovering a module register shows its decoded value on the bottom border.
+    let hovered = self.mouse_pos.filter(|pos| rect.contains(*pos)).and_then(|pos| {
+      let row = (pos.y - rect.y).checked_sub(1)? as usize;
+      let col = (pos.x - rect.x).checked_sub(2)? as usize;
+      let (group, offset) = (col / 26, (col % 26).checked_sub(6)?);
+      (group < per_row && offset % 5 < 4).then_some(start + (row * per_row + group) * 4 + offset / 5).filter(|&addr| addr < end)
+    });
+    let info = hovered.and_then(|addr| {
+      let module = Module::installed(&self.module_defs, &self.sim_state.memory)[Module::addr_to_slot(addr as u16)?]?;
+      let access = module.register(&self.module_defs, addr).map(|reg| reg.access);
+      Some((addr, access, module.module_register_info(&self.module_defs, addr)?))
+    });
+    if let Some((addr, access, (name, desc, kind))) = info {
+      let mut spans = vec![name];
+      if let Some(access) = access {
+        spans.push(format!(" [{}]", access).dark_gray());
+      }
+      spans.push(format!(" {:04x}", self.sim_state.memory[addr]).white());
+      if let Some(value) = kind.decode(&self.sim_state.memory, addr) {
+        spans.push(" = ".dark_gray());
+        spans.push(value);
+      }
+      spans.push(" ".into());
+      spans.push(desc);
+      watch_block = watch_block.title_bottom(Line::from(spans));
+    }
+    frame.render_widget(Clear, rect);
+    frame.render_widget(watch_block, rect);
 
     for addr in (start..end).step_by(4) {
       let i = (addr - start) / 4;
-      let x = rect.x + 2 + (i % 2 * 26) as u16;
-      let y = rect.y + 1 + (i / 2) as u16;
+      let x = rect.x + 2 + (i % per_row * 26) as u16;
+      let y = rect.y + 1 + (i / per_row) as u16;
       if y >= rect.height + rect.y - 1 {
         break;
       }
@@ -1150,6 +2565,11 @@ impl App {
       render_hex(frame, my, x + 11, y, Some(color_from_value(my)));
       render_hex(frame, mz, x + 16, y, Some(color_from_value(mz)));
       render_hex(frame, mw, x + 21, y, Some(color_from_value(mw)));
+
+      if per_row == 1 {
+        let decoded = Rect::new(x + 26, y, rect.right().saturating_sub(x + 28), 1);
+        frame.render_widget(Paragraph::new(Line::from(self.watch_decoded(addr))), decoded);
+      }
     }
   }
 
@@ -1195,6 +2615,10 @@ impl App {
       "[R] ".light_blue(),
       "Step: ".white(),
       "[s] ".light_blue(),
+      "Next: ".white(),
+      "[n] ".light_blue(),
+      "Finish: ".white(),
+      "[f] ".light_blue(),
       "Debug: ".fg(if self.sim_state.debug_mode { Color::Green } else { Color::White }),
       "[d] ".light_blue(),
       "Exit: ".white(),
@@ -1288,6 +2712,24 @@ impl App {
             let max = 0x100 - 0x40;
             self.code_scroll = new.clamp(0, max) as usize;
           }
+          ViewMode::Map => {
+            self.map.zoom_by(1.25f32.powi(lines));
+          }
+          ViewMode::Instruments => {}
+          ViewMode::Users => {
+            let new = self.users_selected as i32 - lines;
+            let max = self.users.len().saturating_sub(1) as i32;
+            self.users_selected = new.clamp(0, max) as usize;
+          }
+          ViewMode::Modules => {
+            let new = self.modules_selected as i32 - lines;
+            self.modules_selected = new.clamp(0, 7) as usize;
+          }
+          ViewMode::Profile => {
+            let new = self.profile_scroll as i32 - lines;
+            let max = self.profile.spots.len().saturating_sub(1) as i32;
+            self.profile_scroll = new.clamp(0, max) as usize;
+          }
         }
       }
     }
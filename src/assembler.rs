@@ -0,0 +1,407 @@
+use meivm2::opcode::{Opcode, RegIndex};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::OnceLock;
+
+use crate::symbols::SymbolTable;
+use crate::wavebin::Section;
+use crate::S;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+  pub line: usize,
+  pub message: String,
+}
+
+impl fmt::Display for AsmError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "line {}: {}", self.line, self.message)
+  }
+}
+
+impl std::error::Error for AsmError {}
+
+fn asm_error(line: usize, message: String) -> AsmError {
+  AsmError { line, message }
+}
+
+/// Number of literal words following a `LoadInc`/`StoreInc`/`GatherInc`/`ScatterInc`
+/// that reads from `Ri`, or 0 for any other instruction.
+pub fn literal_words(opcode: Opcode) -> usize {
+  match opcode {
+    Opcode::LoadInc(src, _, opt) |
+    Opcode::StoreInc(src, _, opt) |
+    Opcode::GatherInc(src, _, opt) |
+    Opcode::ScatterInc(src, _, opt) if RegIndex::from(src as u8) == RegIndex::Ri => {
+      (opt & 0b11) as usize + 1
+    }
+    _ => 0
+  }
+}
+
+/// Lowercases and collapses whitespace and commas so that source text and
+/// `Opcode`'s `Display` output compare equal regardless of spacing.
+fn normalize(text: &str) -> String {
+  text.split(|c: char| c.is_whitespace() || c == ',')
+    .filter(|t| !t.is_empty())
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase()
+}
+
+/// Maps every instruction's printed form back to the lowest word that prints it.
+fn mnemonic_table() -> &'static HashMap<String, u16> {
+  static TABLE: OnceLock<HashMap<String, u16>> = OnceLock::new();
+  TABLE.get_or_init(|| {
+    let mut table = HashMap::new();
+    for word in 0..=u16::MAX {
+      table.entry(normalize(&Opcode::parse(word).to_string())).or_insert(word);
+    }
+    table
+  })
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+  let text = text.trim();
+  let text = text.strip_prefix("0x").unwrap_or(text);
+  u16::from_str_radix(text, 16).ok()
+}
+
+fn is_label_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_values(text: &str) -> Vec<&str> {
+  text.split(|c: char| c.is_whitespace() || c == ',')
+    .filter(|t| !t.is_empty())
+    .collect()
+}
+
+enum Item<'a> {
+  Org(u16),
+  Words(Vec<&'a str>),
+  Instruction(u16, Vec<&'a str>),
+  Const(u16, Vec<&'a str>),
+}
+
+struct Line<'a> {
+  number: usize,
+  text: &'a str,
+  label: Option<&'a str>,
+  item: Option<Item<'a>>,
+}
+
+fn parse_instruction(number: usize, text: &str) -> Result<(u16, Vec<&str>), AsmError> {
+  let (instr, literals) = match text.split_once("<-") {
+    Some((instr, literals)) => (instr, parse_values(literals)),
+    None => (text, Vec::new()),
+  };
+  let word = *mnemonic_table().get(&normalize(instr))
+    .ok_or_else(|| asm_error(number, format!("Unknown instruction: {}", instr.trim())))?;
+  let expected = literal_words(Opcode::parse(word));
+  if literals.len() != expected {
+    return Err(asm_error(number, format!("'{}' takes {} literal words but {} were given", instr.trim(), expected, literals.len())));
+  }
+  Ok((word, literals))
+}
+
+fn parse_line(number: usize, text: &str) -> Result<Line<'_>, AsmError> {
+  let text = text.split(';').next().unwrap().trim();
+  let (label, rest) = match text.split_once(':') {
+    Some((label, rest)) if is_label_name(label.trim()) => (Some(label.trim()), rest.trim()),
+    _ => (None, text),
+  };
+
+  let item = if rest.is_empty() {
+    None
+  } else if let Some(directive) = rest.strip_prefix('.') {
+    let (name, args) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+    match name.to_lowercase().as_str() {
+      "org" => {
+        let addr = parse_number(args)
+          .ok_or_else(|| asm_error(number, format!("Invalid .org address: {}", args.trim())))?;
+        Some(Item::Org(addr))
+      }
+      "word" => Some(Item::Words(parse_values(args))),
+      "const" => {
+        let mut values = parse_values(args);
+        if values.is_empty() {
+          return Err(asm_error(number, S!("Expected a constant register, e.g. .const c0 1, 2")));
+        }
+        let reg = values.remove(0);
+        let index = reg.strip_prefix('c')
+          .and_then(|n| n.parse::<u16>().ok())
+          .filter(|&n| n < 8)
+          .ok_or_else(|| asm_error(number, format!("Invalid constant register: {}", reg)))?;
+        if values.len() > 4 {
+          return Err(asm_error(number, format!("A constant holds at most 4 words but {} were given", values.len())));
+        }
+        Some(Item::Const(index * 4, values))
+      }
+      _ => return Err(asm_error(number, format!("Unknown directive: .{}", name))),
+    }
+  } else {
+    let (word, literals) = parse_instruction(number, rest)?;
+    Some(Item::Instruction(word, literals))
+  };
+
+  Ok(Line { number, text, label, item })
+}
+
+fn resolve(number: usize, value: &str, labels: &HashMap<&str, u16>) -> Result<u16, AsmError> {
+  if let Some(&addr) = labels.get(value) {
+    return Ok(addr);
+  }
+  parse_number(value).ok_or_else(|| asm_error(number, format!("Unknown label or invalid value: {}", value)))
+}
+
+fn section_flags(addr: u16) -> u16 {
+  match addr {
+    0x40..0x100 => Section::CODE,
+    0x300..0x400 => Section::MODULE_CONFIG,
+    _ => Section::DATA,
+  }
+}
+
+/// Assembles `source` into sections. Code starts at 0x40 unless moved with `.org`.
+///
+/// Syntax, one statement per line, `;` starts a comment:
+///   `label:`                      names the current address
+///   `<instruction>`               as printed by the Code view
+///   `<instruction> <- 1234, 5678` an `Ri` literal load followed by its words
+///   `.org 80`                     moves the current address
+///   `.word 1, 2, label`           raw words
+///   `.const c3 1, 2, 3, 4`        initial value of a constant register
+pub fn assemble(source: &str) -> Result<Vec<Section>, AsmError> {
+  assemble_with_symbols(source).map(|(sections, _)| sections)
+}
+
+/// Like [`assemble`], also returning the labels and the source line of every statement.
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<Section>, SymbolTable), AsmError> {
+  let lines = source.lines()
+    .enumerate()
+    .map(|(i, text)| parse_line(i + 1, text))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  // First pass: assign an address to every label.
+  let mut labels = HashMap::new();
+  let mut addr = 0x40u16;
+  for line in lines.iter() {
+    if let Some(Item::Org(org)) = line.item {
+      addr = org;
+    }
+    if let Some(label) = line.label && labels.insert(label, addr).is_some() {
+      return Err(asm_error(line.number, format!("Duplicate label: {}", label)));
+    }
+    match &line.item {
+      Some(Item::Words(values)) => addr = addr.wrapping_add(values.len() as u16),
+      Some(Item::Instruction(_, literals)) => addr = addr.wrapping_add(1 + literals.len() as u16),
+      _ => (),
+    }
+  }
+
+  let mut symbols = SymbolTable::default();
+  for (name, addr) in labels.iter() {
+    symbols.add_label(*addr, name);
+  }
+
+  // Second pass: emit words, starting a new section at every `.org`.
+  let mut sections: Vec<Section> = Vec::new();
+  let mut current = Section { addr: 0x40, flags: Section::CODE, data: Vec::new() };
+  for line in lines.iter() {
+    match &line.item {
+      Some(Item::Org(org)) => {
+        let next = Section { addr: *org, flags: section_flags(*org), data: Vec::new() };
+        let done = std::mem::replace(&mut current, next);
+        if !done.data.is_empty() {
+          sections.push(done);
+        }
+      }
+      Some(Item::Words(values)) => {
+        symbols.add_source(current.addr.wrapping_add(current.data.len() as u16), line.number, line.text);
+        for value in values {
+          current.data.push(resolve(line.number, value, &labels)?);
+        }
+      }
+      Some(Item::Instruction(word, literals)) => {
+        symbols.add_source(current.addr.wrapping_add(current.data.len() as u16), line.number, line.text);
+        current.data.push(*word);
+        for value in literals {
+          current.data.push(resolve(line.number, value, &labels)?);
+        }
+      }
+      Some(Item::Const(addr, values)) => {
+        let data = values.iter()
+          .map(|value| resolve(line.number, value, &labels))
+          .collect::<Result<Vec<_>, _>>()?;
+        sections.push(Section { addr: *addr, flags: Section::CONST_STORE, data });
+      }
+      None => (),
+    }
+  }
+  if !current.data.is_empty() {
+    sections.push(current);
+  }
+
+  Ok((sections, symbols))
+}
+
+/// Assembles a single statement placed at `addr`, for patching memory in place.
+pub fn assemble_line(addr: u16, text: &str) -> Result<Vec<u16>, AsmError> {
+  let source = format!(".org {:04x}\n{}", addr, text);
+  let sections = assemble(&source).map_err(|e| asm_error(1, e.message))?;
+  Ok(sections.into_iter()
+    .filter(|s| s.flags != Section::CONST_STORE)
+    .flat_map(|s| s.data)
+    .collect())
+}
+
+/// Produces assembler source for `memory[start..end]`. Branch targets inside the
+/// range get `L_xxxx` labels, and words that would not assemble back to the same
+/// value are emitted as `.word` so the listing reassembles to identical memory.
+pub fn disassemble(memory: &[u16], start: u16, end: u16) -> String {
+  let end = (end as usize).min(memory.len());
+  let start = (start as usize).min(end);
+
+  // Split the range at the instruction boundaries the CPU sees. A word that does
+  // not reassemble to itself becomes a `.word` run covering its literals too.
+  let mut chunks = Vec::new();
+  let mut addr = start;
+  while addr < end {
+    let word = memory[addr];
+    let opcode = Opcode::parse(word);
+    let literals = literal_words(opcode);
+    let round_trips = mnemonic_table().get(&normalize(&opcode.to_string())) == Some(&word);
+    let len = (1 + literals).min(end - addr);
+    chunks.push((addr, round_trips && addr + literals < end, len));
+    addr += len;
+  }
+
+  // Literal loads into Ri set the PC, so their first literal is a branch target.
+  // Only targets that start a chunk get a label; the rest stay hex.
+  let starts = chunks.iter().map(|&(addr, _, _)| addr as u16).collect::<BTreeSet<_>>();
+  let targets = chunks.iter()
+    .filter(|&&(addr, exact, len)| exact && len > 1 && is_branch(Opcode::parse(memory[addr])))
+    .map(|&(addr, _, _)| memory[addr + 1])
+    .filter(|target| starts.contains(target))
+    .collect::<BTreeSet<_>>();
+
+  let operand = |value: u16| {
+    if targets.contains(&value) { format!("L_{:04x}", value) } else { format!("{:04x}", value) }
+  };
+
+  let mut out = format!("; disassembly of {:04x}..{:04x}\n.org {:04x}\n", start, end, start);
+  for &(addr, exact, len) in chunks.iter() {
+    if targets.contains(&(addr as u16)) {
+      out += &format!("L_{:04x}:\n", addr);
+    }
+    let opcode = Opcode::parse(memory[addr]);
+    let text = opcode.to_string();
+    if !exact {
+      // Not reproducible from its mnemonic, or its literals run past the range.
+      let words = memory[addr..addr + len].iter().map(|w| format!("{:04x}", w)).collect::<Vec<_>>().join(", ");
+      out += &format!("  .word {} ; {}\n", words, text);
+    } else if len == 1 {
+      out += &format!("  {}\n", text);
+    } else {
+      let values = memory[addr + 1..addr + len].iter()
+        .enumerate()
+        .map(|(i, &v)| if i == 0 && is_branch(opcode) { operand(v) } else { format!("{:04x}", v) })
+        .collect::<Vec<_>>()
+        .join(", ");
+      out += &format!("  {} <- {}\n", text, values);
+    }
+  }
+  out
+}
+
+/// A load into `ri`, i.e. a write to the PC.
+pub fn is_branch(opcode: Opcode) -> bool {
+  matches!(opcode, Opcode::LoadInc(_, dst, _) if RegIndex::from(dst as u8) == RegIndex::Ri)
+}
+
+/// A literal load into `ri`: an unconditional jump to its first literal.
+pub fn is_jump(opcode: Opcode) -> bool {
+  is_branch(opcode) && literal_words(opcode) > 0
+}
+
+/// The lowest nonzero instruction word that `pred` accepts, for tests that
+/// need some instruction of a given shape. Panics if there is none.
+#[cfg(test)]
+pub fn find_word(what: &str, pred: impl Fn(u16, Opcode) -> bool) -> u16 {
+  (1..=u16::MAX)
+    .find(|&word| pred(word, Opcode::parse(word)))
+    .unwrap_or_else(|| panic!("no instruction word is {}", what))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn labels_and_directives() {
+    let source = "
+      .const c1 1, 2, 3, 4
+      start:
+      .word 1234, end   ; forward reference
+      .org 80
+      table: .word start, table
+      end:
+    ";
+    let sections = assemble(source).unwrap();
+    assert_eq!(sections, vec![
+      Section { addr: 0x04, flags: Section::CONST_STORE, data: vec![1, 2, 3, 4] },
+      Section { addr: 0x40, flags: Section::CODE, data: vec![0x1234, 0x82] },
+      Section { addr: 0x80, flags: Section::CODE, data: vec![0x40, 0x80] },
+    ]);
+  }
+
+  #[test]
+  fn assembles_printed_instructions() {
+    for word in [0x0000u16, 0x0001, 0x1234, 0x8000, 0xffff] {
+      let opcode = Opcode::parse(word);
+      let literals = vec!["0"; literal_words(opcode)].join(", ");
+      let text = if literals.is_empty() { opcode.to_string() } else { format!("{} <- {}", opcode, literals) };
+      let words = assemble_line(0x40, &text).unwrap();
+      assert_eq!(Opcode::parse(words[0]).to_string(), opcode.to_string());
+      assert_eq!(words.len(), 1 + literal_words(opcode));
+    }
+  }
+
+  #[test]
+  fn disassembly_reassembles() {
+    let mut memory = vec![0u16; 0x100];
+    for (i, word) in memory.iter_mut().enumerate().skip(0x40) {
+      *word = (i as u16).wrapping_mul(0x9e37);
+    }
+    let source = disassemble(&memory, 0x40, 0x100);
+    let sections = assemble(&source).unwrap();
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].addr, 0x40);
+    assert_eq!(sections[0].data, memory[0x40..0x100]);
+  }
+
+  #[test]
+  fn labels_only_instruction_starts() {
+    let jump = find_word("a one-literal jump that reassembles", |word, opcode| {
+      is_jump(opcode) && literal_words(opcode) == 1 && mnemonic_table().get(&normalize(&opcode.to_string())) == Some(&word)
+    });
+    // Jumps to the next instruction, into its own literal, and past the range.
+    let mut memory = vec![0u16; 0x100];
+    memory[0x40..0x48].copy_from_slice(&[jump, 0x44, jump, 0x43, jump, 0x40, jump, 0x90]);
+    let source = disassemble(&memory, 0x40, 0x48);
+    assert!(source.contains("L_0044:") && source.contains("<- L_0044"));
+    assert!(source.contains("<- 0043") && source.contains("<- 0090"));
+    assert_eq!(assemble(&source).unwrap()[0].data, memory[0x40..0x48]);
+  }
+
+  #[test]
+  fn reports_errors_with_line_numbers() {
+    assert_eq!(assemble("a:\na:").unwrap_err().line, 2);
+    assert_eq!(assemble("\n.word nowhere").unwrap_err().line, 2);
+    assert_eq!(assemble(".bogus").unwrap_err().line, 1);
+    assert!(assemble(".const c9 1").is_err());
+  }
+}
@@ -0,0 +1,518 @@
+use std::fmt;
+
+use crate::S;
+
+/// An expression over registers and memory, e.g. `r3.x == 10 && [384] > 0`.
+///
+/// Numbers are hex (with or without `0x`), registers are `c0`..`c7`, `r0`..`r7`
+/// and `ri` with an optional `.x`/`.y`/`.z`/`.w` component, and `[addr]` reads memory.
+/// All arithmetic is on wrapping u16 values; comparisons yield 0 or 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+  Num(u16),
+  Mem(Box<Expr>),
+  Not(Box<Expr>),
+  Neg(Box<Expr>),
+  Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+  Or, And,
+  BitOr, BitXor, BitAnd,
+  Eq, Ne, Lt, Le, Gt, Ge,
+  Add, Sub,
+}
+
+/// Memory address of a register component, e.g. `r3.x` -> 0x2c.
+pub fn register_addr(name: &str) -> Option<u16> {
+  let (reg, comp) = name.split_once('.').unwrap_or((name, "x"));
+  let index = match reg {
+    "ri" => 15,
+    _ => {
+      let n = reg.get(1..)?.parse::<u16>().ok().filter(|&n| n < 8)?;
+      match reg.as_bytes()[0] {
+        b'c' => n,
+        b'r' => n + 8,
+        _ => return None,
+      }
+    }
+  };
+  let comp = match comp {
+    "x" => 0, "y" => 1, "z" => 2, "w" => 3,
+    _ => return None,
+  };
+  Some(index * 4 + comp)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+  Num(u16),
+  Ident(String),
+  Op(&'static str),
+}
+
+const OPERATORS: [&str; 18] = [
+  "||", "&&", "==", "!=", "<=", ">=",
+  "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+  let mut tokens = Vec::new();
+  let mut rest = text.trim_start();
+  while !rest.is_empty() {
+    if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+      tokens.push(Token::Op(op));
+      rest = &rest[op.len()..];
+    } else {
+      let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.')).unwrap_or(rest.len());
+      if end == 0 {
+        return Err(format!("Unexpected character '{}'", rest.chars().next().unwrap()));
+      }
+      let word = &rest[..end];
+      if word.starts_with(|c: char| c.is_ascii_digit()) {
+        let digits = word.strip_prefix("0x").unwrap_or(word);
+        let value = u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid number '{}'", word))?;
+        tokens.push(Token::Num(value));
+      } else {
+        tokens.push(Token::Ident(word.to_string()));
+      }
+      rest = &rest[end..];
+    }
+    rest = rest.trim_start();
+  }
+  Ok(tokens)
+}
+
+struct Parser<'a> {
+  tokens: Vec<Token>,
+  pos: usize,
+  resolve: &'a dyn Fn(&str) -> Option<u16>,
+}
+
+impl Parser<'_> {
+  fn eat(&mut self, op: &str) -> bool {
+    if matches!(self.tokens.get(self.pos), Some(Token::Op(o)) if *o == op) {
+      self.pos += 1;
+      true
+    } else {
+      false
+    }
+  }
+
+  fn binary(&mut self, level: usize) -> Result<Expr, String> {
+    const LEVELS: [&[(&str, BinOp)]; 8] = [
+      &[("||", BinOp::Or)],
+      &[("&&", BinOp::And)],
+      &[("|", BinOp::BitOr)],
+      &[("^", BinOp::BitXor)],
+      &[("&", BinOp::BitAnd)],
+      &[("==", BinOp::Eq), ("!=", BinOp::Ne)],
+      &[("<=", BinOp::Le), (">=", BinOp::Ge), ("<", BinOp::Lt), (">", BinOp::Gt)],
+      &[("+", BinOp::Add), ("-", BinOp::Sub)],
+    ];
+    if level == LEVELS.len() {
+      return self.unary();
+    }
+    let mut lhs = self.binary(level + 1)?;
+    'outer: loop {
+      for &(op, bin) in LEVELS[level] {
+        if self.eat(op) {
+          let rhs = self.binary(level + 1)?;
+          lhs = Expr::Binary(bin, Box::new(lhs), Box::new(rhs));
+          continue 'outer;
+        }
+      }
+      return Ok(lhs);
+    }
+  }
+
+  fn unary(&mut self) -> Result<Expr, String> {
+    if self.eat("!") {
+      return Ok(Expr::Not(Box::new(self.unary()?)));
+    }
+    if self.eat("-") {
+      return Ok(Expr::Neg(Box::new(self.unary()?)));
+    }
+    if self.eat("(") {
+      let expr = self.binary(0)?;
+      if !self.eat(")") {
+        return Err(S!("Expected ')'"));
+      }
+      return Ok(expr);
+    }
+    if self.eat("[") {
+      let expr = self.binary(0)?;
+      if !self.eat("]") {
+        return Err(S!("Expected ']'"));
+      }
+      return Ok(Expr::Mem(Box::new(expr)));
+    }
+    match self.tokens.get(self.pos).cloned() {
+      Some(Token::Num(value)) => {
+        self.pos += 1;
+        Ok(Expr::Num(value))
+      }
+      Some(Token::Ident(name)) => {
+        self.pos += 1;
+        if let Some(addr) = register_addr(&name) {
+          Ok(Expr::Mem(Box::new(Expr::Num(addr))))
+        } else if let Some(value) = (self.resolve)(&name).or_else(|| u16::from_str_radix(&name, 16).ok()) {
+          Ok(Expr::Num(value))
+        } else {
+          Err(format!("Unknown name '{}'", name))
+        }
+      }
+      Some(Token::Op(op)) => Err(format!("Unexpected '{}'", op)),
+      None => Err(S!("Unexpected end of expression")),
+    }
+  }
+}
+
+impl Expr {
+  /// Parses `text`, looking up names that are not registers with `resolve` (e.g. symbols).
+  pub fn parse(text: &str, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<Expr, String> {
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0, resolve };
+    let expr = parser.binary(0)?;
+    if parser.pos != parser.tokens.len() {
+      return Err(format!("Unexpected trailing input in '{}'", text));
+    }
+    Ok(expr)
+  }
+
+  pub fn eval(&self, read: &mut dyn FnMut(u16) -> u16) -> u16 {
+    match self {
+      Expr::Num(value) => *value,
+      Expr::Mem(addr) => {
+        let addr = addr.eval(read);
+        read(addr)
+      }
+      Expr::Not(e) => (e.eval(read) == 0) as u16,
+      Expr::Neg(e) => e.eval(read).wrapping_neg(),
+      Expr::Binary(op, l, r) => {
+        let l = l.eval(read);
+        // Short-circuit so `&&`/`||` only read memory they need.
+        match op {
+          BinOp::And if l == 0 => return 0,
+          BinOp::Or if l != 0 => return 1,
+          _ => (),
+        }
+        let r = r.eval(read);
+        match op {
+          BinOp::Or | BinOp::And => (r != 0) as u16,
+          BinOp::BitOr => l | r,
+          BinOp::BitXor => l ^ r,
+          BinOp::BitAnd => l & r,
+          BinOp::Eq => (l == r) as u16,
+          BinOp::Ne => (l != r) as u16,
+          BinOp::Lt => (l < r) as u16,
+          BinOp::Le => (l <= r) as u16,
+          BinOp::Gt => (l > r) as u16,
+          BinOp::Ge => (l >= r) as u16,
+          BinOp::Add => l.wrapping_add(r),
+          BinOp::Sub => l.wrapping_sub(r),
+        }
+      }
+    }
+  }
+}
+
+/// A tracepoint message; `{expr}` parts are evaluated and printed in hex on each hit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TracePart {
+  Text(String),
+  Expr(Expr),
+}
+
+pub fn parse_trace_message(text: &str, resolve: &dyn Fn(&str) -> Option<u16>) -> Result<Vec<TracePart>, String> {
+  let mut parts = Vec::new();
+  let mut rest = text;
+  while let Some(open) = rest.find('{') {
+    let close = rest[open..].find('}').ok_or_else(|| S!("Unclosed '{' in message"))? + open;
+    if open > 0 {
+      parts.push(TracePart::Text(rest[..open].to_string()));
+    }
+    parts.push(TracePart::Expr(Expr::parse(&rest[open + 1..close], resolve)?));
+    rest = &rest[close + 1..];
+  }
+  if !rest.is_empty() {
+    parts.push(TracePart::Text(rest.to_string()));
+  }
+  Ok(parts)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakpointKind {
+  Break,
+  /// Log a message and keep running.
+  Trace(String, Vec<TracePart>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+  pub addr: u16,
+  pub kind: BreakpointKind,
+  /// Source text and parsed form of the condition.
+  pub condition: Option<(String, Expr)>,
+  /// Number of hits to skip before the breakpoint triggers.
+  pub ignore_count: u32,
+  pub hits: u32,
+}
+
+impl Breakpoint {
+  pub fn new(addr: u16) -> Self {
+    Breakpoint {
+      addr,
+      kind: BreakpointKind::Break,
+      condition: None,
+      ignore_count: 0,
+      hits: 0,
+    }
+  }
+
+  /// Equal apart from the hit count.
+  pub fn same_as(&self, other: &Breakpoint) -> bool {
+    self.addr == other.addr
+      && self.kind == other.kind
+      && self.condition == other.condition
+      && self.ignore_count == other.ignore_count
+  }
+
+  /// A plain breakpoint can be left to the VM; anything else is checked by the sim thread.
+  pub fn is_plain(&self) -> bool {
+    self.kind == BreakpointKind::Break && self.condition.is_none() && self.ignore_count == 0
+  }
+
+  pub fn glyph(&self) -> &'static str {
+    match (&self.kind, &self.condition, self.ignore_count) {
+      (BreakpointKind::Trace(..), _, _) => "◇",
+      (_, Some(_), _) => "◆",
+      (_, None, 0) => "●",
+      (_, None, _) => "◉",
+    }
+  }
+
+  /// Called when execution reaches `addr`. Returns whether the breakpoint fires,
+  /// counting the hit only if the condition holds.
+  pub fn hit(&mut self, read: &mut dyn FnMut(u16) -> u16) -> bool {
+    if let Some((_, cond)) = &self.condition && cond.eval(read) == 0 {
+      return false;
+    }
+    self.hits += 1;
+    self.hits > self.ignore_count
+  }
+
+  pub fn trace_message(&self, read: &mut dyn FnMut(u16) -> u16) -> Option<String> {
+    match &self.kind {
+      BreakpointKind::Trace(_, parts) => Some(parts.iter().map(|part| match part {
+        TracePart::Text(text) => text.clone(),
+        TracePart::Expr(expr) => format!("{:04x}", expr.eval(read)),
+      }).collect()),
+      BreakpointKind::Break => None,
+    }
+  }
+}
+
+impl fmt::Display for Breakpoint {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} {:04x}", self.glyph(), self.addr)?;
+    if self.ignore_count > 0 {
+      write!(f, " ignore {}", self.ignore_count)?;
+    }
+    if let Some((text, _)) = &self.condition {
+      write!(f, " if {}", text)?;
+    }
+    if let BreakpointKind::Trace(text, _) = &self.kind {
+      write!(f, " trace \"{}\"", text)?;
+    }
+    write!(f, " (hits: {})", self.hits)
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+  Read,
+  Write,
+  ReadWrite,
+}
+
+impl Access {
+  pub fn parse(text: &str) -> Option<Access> {
+    match text {
+      "r" => Some(Access::Read),
+      "w" => Some(Access::Write),
+      "rw" => Some(Access::ReadWrite),
+      _ => None,
+    }
+  }
+
+  fn includes(self, write: bool) -> bool {
+    match self {
+      Access::Read => !write,
+      Access::Write => write,
+      Access::ReadWrite => true,
+    }
+  }
+}
+
+impl fmt::Display for Access {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Access::Read => write!(f, "r"),
+      Access::Write => write!(f, "w"),
+      Access::ReadWrite => write!(f, "rw"),
+    }
+  }
+}
+
+/// Stops execution when any word in `addr..addr + len` is accessed.
+///
+/// Writes are found by diffing the watched words around each tick. Reads are
+/// found by decoding the load at the PC before it executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+  pub addr: u16,
+  pub len: u16,
+  pub access: Access,
+  pub condition: Option<(String, Expr)>,
+}
+
+impl Watchpoint {
+  pub fn new(addr: u16, len: u16, access: Access) -> Self {
+    Watchpoint { addr, len: len.max(1), access, condition: None }
+  }
+
+  pub fn contains(&self, addr: u16) -> bool {
+    addr.wrapping_sub(self.addr) < self.len
+  }
+
+  /// Checks an access to `addr`. The condition is evaluated after the access.
+  pub fn hit(&self, addr: u16, write: bool, read: &mut dyn FnMut(u16) -> u16) -> bool {
+    if !self.contains(addr) || !self.access.includes(write) {
+      return false;
+    }
+    match &self.condition {
+      Some((_, cond)) => cond.eval(read) != 0,
+      None => true,
+    }
+  }
+}
+
+impl fmt::Display for Watchpoint {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:04x}..{:04x} {}", self.addr, self.addr as usize + self.len as usize, self.access)?;
+    if let Some((text, _)) = &self.condition {
+      write!(f, " if {}", text)?;
+    }
+    Ok(())
+  }
+}
+
+/// A fired watchpoint: the PC of the instruction that made the access and the word before and after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+  pub pc: u16,
+  pub addr: u16,
+  pub old: u16,
+  pub new: u16,
+  pub write: bool,
+}
+
+impl fmt::Display for WatchHit {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.write {
+      write!(f, "Watchpoint: {:04x} written at pc {:04x}: {:04x} -> {:04x}", self.addr, self.pc, self.old, self.new)
+    } else {
+      write!(f, "Watchpoint: {:04x} read at pc {:04x}: {:04x}", self.addr, self.pc, self.old)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn eval(text: &str, memory: &[u16]) -> u16 {
+    Expr::parse(text, &|name| if name == "counter" { Some(0x40) } else { None })
+      .unwrap()
+      .eval(&mut |addr| memory[addr as usize])
+  }
+
+  #[test]
+  fn register_addresses() {
+    assert_eq!(register_addr("c0"), Some(0x00));
+    assert_eq!(register_addr("r3.x"), Some(0x2c));
+    assert_eq!(register_addr("r3.w"), Some(0x2f));
+    assert_eq!(register_addr("ri.x"), Some(0x3c));
+    assert_eq!(register_addr("r8"), None);
+    assert_eq!(register_addr("r3.q"), None);
+  }
+
+  #[test]
+  fn evaluates_conditions() {
+    let mut memory = vec![0u16; 0x400];
+    memory[0x2c] = 0x10;
+    memory[0x384] = 5;
+    memory[0x40] = 3;
+    assert_eq!(eval("r3.x == 0x10 && [0x384] > 0", &memory), 1);
+    assert_eq!(eval("r3.x == 10 && [384] > 5", &memory), 0);
+    assert_eq!(eval("[counter] + 1 == 4", &memory), 1);
+    assert_eq!(eval("!(r3 != 10) || [0]", &memory), 1);
+    assert_eq!(eval("1 + 2 == 3 & 1", &memory), 1);
+    assert_eq!(eval("0 - 1", &memory), 0xffff);
+  }
+
+  #[test]
+  fn rejects_bad_expressions() {
+    let resolve = |_: &str| None;
+    assert!(Expr::parse("r3.x ==", &resolve).is_err());
+    assert!(Expr::parse("[384", &resolve).is_err());
+    assert!(Expr::parse("nowhere > 1", &resolve).is_err());
+    assert!(Expr::parse("1 2", &resolve).is_err());
+  }
+
+  #[test]
+  fn ignore_count_and_condition() {
+    let memory = [1u16; 4];
+    let mut bp = Breakpoint::new(0x40);
+    bp.ignore_count = 2;
+    bp.condition = Some((S!("c0"), Expr::parse("c0", &|_| None).unwrap()));
+    let mut read = |addr: u16| memory[addr as usize];
+    assert!(!bp.hit(&mut read));
+    assert!(!bp.hit(&mut read));
+    assert!(bp.hit(&mut read));
+  }
+
+  #[test]
+  fn formats_trace_messages() {
+    let memory = [0x2au16; 0x40];
+    let mut bp = Breakpoint::new(0x40);
+    let parts = parse_trace_message("r0={r0} done", &|_| None).unwrap();
+    bp.kind = BreakpointKind::Trace(S!("r0={r0} done"), parts);
+    assert_eq!(bp.trace_message(&mut |addr| memory[addr as usize]), Some(S!("r0=002a done")));
+  }
+
+  #[test]
+  fn watchpoint_ranges_and_access() {
+    let mut memory = [0u16; 0x40];
+    let mut wp = Watchpoint::new(0x10, 4, Access::Write);
+    let mut read = |addr: u16| memory[addr as usize];
+    assert!(wp.hit(0x10, true, &mut read));
+    assert!(wp.hit(0x13, true, &mut read));
+    assert!(!wp.hit(0x14, true, &mut read));
+    assert!(!wp.hit(0x0f, true, &mut read));
+    assert!(!wp.hit(0x10, false, &mut read));
+
+    wp.access = Access::ReadWrite;
+    wp.condition = Some((S!("[10] == 5"), Expr::parse("[10] == 5", &|_| None).unwrap()));
+    assert!(!wp.hit(0x10, false, &mut |addr| memory[addr as usize]));
+    memory[0x10] = 5;
+    assert!(wp.hit(0x10, false, &mut |addr| memory[addr as usize]));
+
+    // Ranges may wrap around the top of memory.
+    let wp = Watchpoint::new(0xfffe, 4, Access::Read);
+    assert!(wp.contains(0xffff));
+    assert!(wp.contains(0x0001));
+    assert!(!wp.contains(0x0002));
+  }
+}
@@ -0,0 +1,229 @@
+use meivm2::opcode::Opcode;
+use std::collections::BTreeMap;
+
+use crate::assembler::{is_branch, is_jump, literal_words};
+use crate::symbols::SymbolTable;
+use crate::S;
+
+pub const CODE_START: u16 = 0x40;
+pub const CODE_END: u16 = 0x100;
+
+/// Outcomes seen for a branch instruction that can go either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BranchOutcome {
+  pub taken: u64,
+  pub not_taken: u64,
+}
+
+/// Which code addresses have executed and which way each branch went.
+/// Kept across resets so several runs add up; `coverage reset` and loading a
+/// snapshot clear it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coverage {
+  /// Execution count per address in `CODE_START..CODE_END`.
+  pub hits: Vec<u64>,
+  pub branches: BTreeMap<u16, BranchOutcome>,
+  /// Off by default, as recording needs the sim to run one tick at a time.
+  pub enabled: bool,
+  pub dirty: bool,
+}
+
+impl Default for Coverage {
+  fn default() -> Self {
+    Coverage {
+      hits: vec![0; (CODE_END - CODE_START) as usize],
+      branches: BTreeMap::new(),
+      enabled: false,
+      dirty: false,
+    }
+  }
+}
+
+impl Coverage {
+  /// Records the instruction at `pc` executing and the PC moving to `new_pc`.
+  /// `next` is the address right after the instruction.
+  pub fn record(&mut self, pc: u16, opcode: Opcode, next: u16, new_pc: u16) {
+    if !(CODE_START..CODE_END).contains(&pc) {
+      return;
+    }
+    self.hits[(pc - CODE_START) as usize] += 1;
+    if two_way(opcode) {
+      let outcome = self.branches.entry(pc).or_default();
+      if new_pc == next {
+        outcome.not_taken += 1;
+      } else {
+        outcome.taken += 1;
+      }
+    }
+    self.dirty = true;
+  }
+
+  pub fn reset(&mut self) {
+    *self = Coverage { enabled: self.enabled, dirty: true, ..Coverage::default() };
+  }
+
+  pub fn hits_at(&self, addr: u16) -> Option<u64> {
+    (CODE_START..CODE_END).contains(&addr).then(|| self.hits[(addr - CODE_START) as usize])
+  }
+}
+
+/// A branch that can either jump or fall through. Literal jumps always go
+/// to the same place, so they have no outcome worth tracking.
+fn two_way(opcode: Opcode) -> bool {
+  is_branch(opcode) && !is_jump(opcode)
+}
+
+/// Addresses of the instructions in the code region, skipping literal words
+/// and empty (zero) words.
+pub fn instructions(memory: &[u16]) -> Vec<u16> {
+  let mut addrs = Vec::new();
+  let mut addr = CODE_START;
+  while addr < CODE_END {
+    let word = memory[addr as usize];
+    let opcode = Opcode::parse(word);
+    if word != 0 {
+      addrs.push(addr);
+    }
+    addr += 1 + literal_words(opcode) as u16;
+  }
+  addrs
+}
+
+/// Plain-text report: a summary, then every instruction never executed and
+/// every branch that only went one way.
+pub fn report_text(coverage: &Coverage, memory: &[u16], symbols: &SymbolTable) -> String {
+  let addrs = instructions(memory);
+  let executed = addrs.iter().filter(|&&addr| coverage.hits_at(addr).unwrap_or(0) > 0).count();
+  let branches = addrs.iter().filter(|&&addr| two_way(Opcode::parse(memory[addr as usize]))).count();
+  let both_ways = coverage.branches.values().filter(|o| o.taken > 0 && o.not_taken > 0).count();
+
+  let describe = |addr: u16| {
+    let mut text = format!("{:04x}: {}", addr, Opcode::parse(memory[addr as usize]));
+    if let Some(label) = symbols.label_at(addr) {
+      text = format!("{} ({})", text, label);
+    }
+    if let Some((line, source)) = symbols.source_at(addr) {
+      text += &format!("  ; {}: {}", line, source);
+    }
+    text
+  };
+
+  let mut out = format!("Instructions: {}/{} executed ({:.1}%)\n", executed, addrs.len(), percent(executed, addrs.len()));
+  out += &format!("Branches: {}/{} went both ways ({:.1}%)\n", both_ways, branches, percent(both_ways, branches));
+  for &addr in addrs.iter().filter(|&&addr| coverage.hits_at(addr) == Some(0)) {
+    out += &format!("never executed  {}\n", describe(addr));
+  }
+  for &addr in addrs.iter().filter(|&&addr| two_way(Opcode::parse(memory[addr as usize]))) {
+    let outcome = coverage.branches.get(&addr).copied().unwrap_or_default();
+    let missing = match (outcome.taken > 0, outcome.not_taken > 0) {
+      (true, true) | (false, false) => continue,
+      (true, false) => "never fell through",
+      (false, true) => "never taken",
+    };
+    out += &format!("{:<16}{}\n", missing, describe(addr));
+  }
+  out
+}
+
+/// lcov tracefile for `source`. Lines come from the symbol table's source map;
+/// without one, the address is used as the line number.
+pub fn report_lcov(coverage: &Coverage, memory: &[u16], symbols: &SymbolTable, source: &str) -> String {
+  let line_of = |addr: u16| symbols.source_at(addr).map(|(line, _)| *line).unwrap_or(addr as usize);
+  let mut lines: BTreeMap<usize, u64> = BTreeMap::new();
+  let mut out = format!("TN:\nSF:{}\n", source);
+  let (mut found, mut hit) = (0, 0);
+  for addr in instructions(memory) {
+    let line = line_of(addr);
+    let count = coverage.hits_at(addr).unwrap_or(0);
+    *lines.entry(line).or_default() += count;
+    if two_way(Opcode::parse(memory[addr as usize])) {
+      let outcome = coverage.branches.get(&addr).copied().unwrap_or_default();
+      for (branch, taken) in [(0, outcome.taken), (1, outcome.not_taken)] {
+        let taken = if count == 0 { S!("-") } else { taken.to_string() };
+        out += &format!("BRDA:{},{},{},{}\n", line, addr, branch, taken);
+      }
+      found += 2;
+      hit += (outcome.taken > 0) as usize + (outcome.not_taken > 0) as usize;
+    }
+  }
+  out += &format!("BRF:{}\nBRH:{}\n", found, hit);
+  for (line, count) in lines.iter() {
+    out += &format!("DA:{},{}\n", line, count);
+  }
+  out += &format!("LF:{}\nLH:{}\nend_of_record\n", lines.len(), lines.values().filter(|&&c| c > 0).count());
+  out
+}
+
+fn percent(part: usize, total: usize) -> f32 {
+  if total == 0 { 100.0 } else { part as f32 * 100.0 / total as f32 }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::assembler::find_word;
+
+  /// Some word that decodes to a single-word instruction that is not a branch.
+  fn plain_word() -> u16 {
+    find_word("a plain single-word instruction", |_, opcode| literal_words(opcode) == 0 && !is_branch(opcode))
+  }
+
+  #[test]
+  fn reports_executed_and_missed_instructions() {
+    let word = plain_word();
+    let mut memory = vec![0u16; 0x2000];
+    memory[0x40..0x44].fill(word);
+    assert_eq!(instructions(&memory), vec![0x40, 0x41, 0x42, 0x43]);
+
+    let mut coverage = Coverage::default();
+    let opcode = Opcode::parse(word);
+    coverage.record(0x40, opcode, 0x41, 0x41);
+    coverage.record(0x41, opcode, 0x42, 0x42);
+    coverage.record(0x41, opcode, 0x42, 0x42);
+    coverage.record(0x300, opcode, 0x301, 0x301);
+    assert_eq!(coverage.hits_at(0x41), Some(2));
+    assert_eq!(coverage.hits_at(0x300), None);
+    assert!(coverage.branches.is_empty());
+
+    let mut symbols = SymbolTable::default();
+    symbols.add_label(0x42, "skipped");
+    symbols.add_source(0x42, 7, "nop");
+    let text = report_text(&coverage, &memory, &symbols);
+    assert!(text.starts_with("Instructions: 2/4 executed (50.0%)\n"));
+    assert!(text.contains("never executed  0042: "));
+    assert!(text.contains("(skipped)  ; 7: nop"));
+
+    let lcov = report_lcov(&coverage, &memory, &symbols, "prog.wasm");
+    assert!(lcov.starts_with("TN:\nSF:prog.wasm\n"));
+    assert!(lcov.contains("DA:64,1\nDA:65,2\nDA:67,0\n"));
+    assert!(lcov.contains("DA:7,0\n"));
+    assert!(lcov.ends_with("LF:4\nLH:2\nend_of_record\n"));
+  }
+
+  #[test]
+  fn records_branch_outcomes() {
+    let word = find_word("a two-way branch", |_, opcode| two_way(opcode));
+    let opcode = Opcode::parse(word);
+    let next = 0x40 + 1 + literal_words(opcode) as u16;
+    let mut coverage = Coverage::default();
+    coverage.record(0x40, opcode, next, 0x80);
+    assert_eq!(coverage.branches[&0x40], BranchOutcome { taken: 1, not_taken: 0 });
+    coverage.record(0x40, opcode, next, next);
+    assert_eq!(coverage.branches[&0x40], BranchOutcome { taken: 1, not_taken: 1 });
+  }
+
+  #[test]
+  fn leaves_jumps_out_of_branches() {
+    let word = find_word("a literal jump", |_, opcode| is_jump(opcode));
+    let opcode = Opcode::parse(word);
+    let mut memory = vec![0u16; 0x2000];
+    memory[0x40] = word;
+    memory[0x41] = 0x80;
+    let mut coverage = Coverage::default();
+    coverage.record(0x40, opcode, 0x40 + 1 + literal_words(opcode) as u16, 0x80);
+    assert!(coverage.branches.is_empty());
+    let symbols = SymbolTable::default();
+    assert!(report_text(&coverage, &memory, &symbols).contains("Branches: 0/0"));
+    assert!(report_lcov(&coverage, &memory, &symbols, "prog.wasm").contains("BRF:0\nBRH:0\n"));
+  }
+}
@@ -0,0 +1,322 @@
+use meivm2::{MEM_SHARED_SIZE_U, SimulationVM};
+use meivm2::opcode::{Opcode, RegIndex};
+use std::sync::mpsc;
+
+use crate::assembler::{is_branch, is_jump, literal_words};
+use crate::breakpoints::{Access, Breakpoint, BreakpointKind, WatchHit, Watchpoint};
+use crate::coverage::Coverage;
+use crate::history::History;
+use crate::profile::Profiler;
+use crate::trace::{TraceError, TraceRecord, TraceWriter};
+use crate::{SimOutput, StopReason};
+
+/// Deepest shadow call stack kept for `finish`.
+const MAX_CALL_DEPTH: usize = 256;
+
+/// Debugger state owned by the `sim` thread and checked around every tick.
+#[derive(Debug)]
+pub struct Debugger<D> {
+  pub breakpoints: Vec<Breakpoint>,
+  pub watchpoints: Vec<Watchpoint>,
+  /// One-shot breakpoints used by `next`, `finish`, `until` and run to cursor.
+  pub temp_breaks: Vec<u16>,
+  /// Return addresses of the calls made so far, innermost last.
+  pub calls: Vec<u16>,
+  pub history: History<D>,
+  pub trace: Option<TraceWriter>,
+  pub profiler: Profiler,
+  pub coverage: Coverage,
+  /// Ticks run through the debugger since the trace was opened, used to
+  /// number trace records.
+  pub ticks: u64,
+  /// Memory as of the last tick, for finding what the next one writes.
+  shadow: Vec<u16>,
+  synced: bool,
+  last_pc: u16,
+}
+
+pub fn read_pc(sim_vm: &mut SimulationVM, user: u64) -> u16 {
+  sim_vm.user_read(user, 0x3c) & 0x1fff
+}
+
+/// Address of the instruction following the one at `pc`, skipping its literals.
+pub fn next_instruction(sim_vm: &mut SimulationVM, user: u64, pc: u16) -> u16 {
+  let opcode = Opcode::parse(sim_vm.user_read(user, pc));
+  pc.wrapping_add(1 + literal_words(opcode) as u16)
+}
+
+/// A jump is a call when the instruction right before it loads the jump's
+/// return address as a literal, as in `ld rN <- ret` followed by `ld ri <- sub`.
+pub fn is_call(sim_vm: &mut SimulationVM, user: u64, pc: u16) -> bool {
+  if !is_jump(Opcode::parse(sim_vm.user_read(user, pc))) {
+    return false;
+  }
+  let ret = next_instruction(sim_vm, user, pc);
+  (2..=5u16).any(|back| {
+    let start = pc.wrapping_sub(back);
+    let opcode = Opcode::parse(sim_vm.user_read(user, start));
+    let literals = literal_words(opcode) as u16;
+    literals + 1 == back && !is_branch(opcode)
+      && (1..=literals).any(|i| sim_vm.user_read(user, start.wrapping_add(i)) == ret)
+  })
+}
+
+impl<D> Debugger<D> {
+  pub fn new(history: History<D>) -> Self {
+    Debugger {
+      breakpoints: Vec::new(),
+      watchpoints: Vec::new(),
+      temp_breaks: Vec::new(),
+      calls: Vec::new(),
+      history,
+      trace: None,
+      profiler: Profiler::default(),
+      coverage: Coverage::default(),
+      ticks: 0,
+      shadow: vec![0; MEM_SHARED_SIZE_U],
+      synced: false,
+      last_pc: 0xffff,
+    }
+  }
+
+  /// Whether running has to go one tick at a time so the debugger sees every
+  /// instruction. Otherwise the VM can run a whole frame in one call.
+  pub fn inspects_ticks(&self) -> bool {
+    !self.temp_breaks.is_empty()
+      || !self.watchpoints.is_empty()
+      || self.breakpoints.iter().any(|bp| !bp.is_plain())
+      || self.history.depth() > 0
+      || self.trace.is_some()
+      || self.profiler.enabled
+      || self.coverage.enabled
+  }
+
+  /// Memory may have been changed outside of a tick; re-read it before the next one.
+  pub fn invalidate(&mut self) {
+    self.synced = false;
+  }
+
+  /// Runs one VM tick, recording its writes for the history and the trace file.
+  fn tick_vm(&mut self, sim_vm: &mut SimulationVM, user: u64) -> Result<(), TraceError> {
+    self.ticks += 1;
+    let pc = read_pc(sim_vm, user);
+    let word = sim_vm.user_read(user, pc);
+    let opcode = Opcode::parse(word);
+    let sleeping = sim_vm.make_user(user).proc.sleep_for > 0;
+    if self.profiler.enabled {
+      self.profiler.record(pc, opcode, sleeping);
+    }
+    if self.history.depth() == 0 && self.trace.is_none() {
+      sim_vm.tick(1);
+      self.record_coverage(sim_vm, user, pc, opcode, sleeping);
+      return Ok(());
+    }
+    if !self.synced {
+      for (i, word) in self.shadow.iter_mut().enumerate() {
+        *word = sim_vm.user_read(user, i as u16);
+      }
+      self.synced = true;
+    }
+    self.history.before_tick(sim_vm, user);
+    sim_vm.tick(1);
+    self.record_coverage(sim_vm, user, pc, opcode, sleeping);
+
+    let mut changes = Vec::new();
+    for (i, word) in self.shadow.iter_mut().enumerate() {
+      let new = sim_vm.user_read(user, i as u16);
+      if new != *word {
+        changes.push((i as u16, *word, new));
+        *word = new;
+      }
+    }
+    self.history.after_tick(&changes);
+    // Sleeping ticks execute nothing and leave memory alone.
+    if let Some(trace) = &mut self.trace && !changes.is_empty() {
+      trace.write(&TraceRecord::new(user, self.ticks, pc, word, &changes))?;
+    }
+    Ok(())
+  }
+
+  fn record_coverage(&mut self, sim_vm: &mut SimulationVM, user: u64, pc: u16, opcode: Opcode, sleeping: bool) {
+    if self.coverage.enabled && !sleeping {
+      let next = pc.wrapping_add(1 + literal_words(opcode) as u16);
+      self.coverage.record(pc, opcode, next, read_pc(sim_vm, user));
+    }
+  }
+
+  /// Runs a tick, reporting and closing the trace file if writing it fails.
+  fn tick_or_report(&mut self, sim_vm: &mut SimulationVM, user: u64, sim_tx: &mpsc::Sender<SimOutput>) {
+    if let Err(err) = self.tick_vm(sim_vm, user) {
+      let path = self.trace.take().map(|trace| trace.path).unwrap_or_default();
+      sim_tx.send(SimOutput::Error(format!("Trace to {} stopped: {}", path, err))).unwrap();
+    }
+  }
+
+  /// Replaces the breakpoints, keeping hit counts of the ones that did not change.
+  pub fn set_breakpoints(&mut self, mut bps: Vec<Breakpoint>) {
+    for bp in bps.iter_mut() {
+      if let Some(old) = self.breakpoints.iter().find(|old| old.same_as(bp)) {
+        bp.hits = old.hits;
+      }
+    }
+    self.breakpoints = bps;
+  }
+
+  /// Temporary breakpoint to step over the instruction at the PC, if it is a call.
+  /// Anything else, including plain jumps, is stepped into.
+  pub fn step_over_target(&self, sim_vm: &mut SimulationVM, user: u64) -> Option<u16> {
+    let pc = read_pc(sim_vm, user);
+    is_call(sim_vm, user, pc).then(|| next_instruction(sim_vm, user, pc))
+  }
+
+  /// Updates the shadow call stack after the PC moved from `pc` to `new_pc`.
+  /// A call pushes its return address; reaching any recorded address pops
+  /// everything above it.
+  fn record_step(&mut self, sim_vm: &mut SimulationVM, user: u64, pc: u16, new_pc: u16) {
+    if new_pc == pc {
+      return;
+    }
+    if let Some(i) = self.calls.iter().rposition(|&addr| addr == new_pc) {
+      self.calls.truncate(i);
+    } else if is_call(sim_vm, user, pc) {
+      let ret = next_instruction(sim_vm, user, pc);
+      if new_pc != ret {
+        if self.calls.len() == MAX_CALL_DEPTH {
+          self.calls.remove(0);
+        }
+        self.calls.push(ret);
+      }
+    }
+  }
+
+  /// Runs `ticks` ticks at once, as `Step` does, keeping the call stack current.
+  pub fn step(&mut self, sim_vm: &mut SimulationVM, user: u64, ticks: usize, sim_tx: &mpsc::Sender<SimOutput>) {
+    let pc = read_pc(sim_vm, user);
+    for _ in 0..ticks {
+      self.tick_or_report(sim_vm, user, sim_tx);
+    }
+    let new_pc = read_pc(sim_vm, user);
+    self.record_step(sim_vm, user, pc, new_pc);
+  }
+
+  /// Runs a single tick, checking watchpoints around it and then the breakpoints
+  /// the VM does not handle itself. Returns why execution should stop, if it should.
+  pub fn tick(&mut self, sim_vm: &mut SimulationVM, user: u64, sim_tx: &mpsc::Sender<SimOutput>) -> Option<StopReason> {
+    let pc = read_pc(sim_vm, user);
+    let opcode = Opcode::parse(sim_vm.user_read(user, pc));
+    let reads = if self.watchpoints.is_empty() { None } else { load_range(sim_vm, user, opcode) };
+    let before = self.watchpoints.iter()
+      .map(|wp| (0..wp.len).map(|i| sim_vm.user_read(user, wp.addr.wrapping_add(i))).collect::<Vec<_>>())
+      .collect::<Vec<_>>();
+    self.tick_or_report(sim_vm, user, sim_tx);
+    let new_pc = read_pc(sim_vm, user);
+    self.record_step(sim_vm, user, pc, new_pc);
+
+    let mut stop = None;
+    for (wp, before) in self.watchpoints.iter().zip(before) {
+      for (i, old) in before.into_iter().enumerate() {
+        let addr = wp.addr.wrapping_add(i as u16);
+        let new = sim_vm.user_read(user, addr);
+        let write = old != new;
+        let read = reads.is_some_and(|(start, len)| addr.wrapping_sub(start) < len);
+        if !write && !read {
+          continue;
+        }
+        if wp.hit(addr, write, &mut |addr| sim_vm.user_read(user, addr)) {
+          sim_tx.send(SimOutput::WatchpointHit(user, WatchHit { pc, addr, old, new, write })).unwrap();
+          stop = Some(StopReason::Watchpoint(addr, if write { Access::Write } else { Access::Read }));
+        }
+      }
+    }
+    if let Some(pc) = self.check_breakpoints(sim_vm, user, sim_tx) {
+      stop = Some(StopReason::Breakpoint(pc));
+    }
+    if new_pc != pc && self.temp_breaks.contains(&new_pc) {
+      stop = stop.or(Some(StopReason::Breakpoint(new_pc)));
+    }
+    if stop.is_some() {
+      self.temp_breaks.clear();
+    }
+    stop
+  }
+
+  /// Undoes ticks until the PC moves back by one instruction. Returns false
+  /// when there is no recorded history left.
+  pub fn reverse_step(&mut self, sim_vm: &mut SimulationVM, user: u64) -> bool {
+    self.reverse(sim_vm, user).is_some()
+  }
+
+  /// Undoes instructions until the PC reaches a breakpoint or an undone write
+  /// touches a watchpoint. Returns `None` at the start of the recorded history.
+  pub fn reverse_continue(&mut self, sim_vm: &mut SimulationVM, user: u64) -> Option<StopReason> {
+    loop {
+      let writes = self.reverse(sim_vm, user)?;
+      if let Some(&addr) = writes.iter().find(|&&addr| {
+        self.watchpoints.iter().any(|wp| wp.contains(addr) && wp.access != Access::Read)
+      }) {
+        return Some(StopReason::Watchpoint(addr, Access::Write));
+      }
+      let pc = read_pc(sim_vm, user);
+      let at_breakpoint = self.breakpoints.iter().any(|bp| {
+        bp.addr == pc && bp.kind == BreakpointKind::Break && bp.condition.as_ref()
+          .is_none_or(|(_, cond)| cond.eval(&mut |addr| sim_vm.user_read(user, addr)) != 0)
+      });
+      if at_breakpoint {
+        return Some(StopReason::Breakpoint(pc));
+      }
+    }
+  }
+
+  /// Undoes one instruction, returning every address it and any sleep ticks before it wrote.
+  fn reverse(&mut self, sim_vm: &mut SimulationVM, user: u64) -> Option<Vec<u16>> {
+    let pc = read_pc(sim_vm, user);
+    let mut writes = Vec::new();
+    self.synced = false;
+    loop {
+      writes.extend(self.history.undo(sim_vm, user)?);
+      if read_pc(sim_vm, user) != pc {
+        break;
+      }
+    }
+    // Do not report the breakpoint we have just stepped back onto.
+    self.last_pc = read_pc(sim_vm, user);
+    Some(writes)
+  }
+
+  /// Checks the breakpoints the VM does not handle itself when the PC moves to a
+  /// new address. Tracepoints report their message; returns the address to stop at.
+  fn check_breakpoints(&mut self, sim_vm: &mut SimulationVM, user: u64, sim_tx: &mpsc::Sender<SimOutput>) -> Option<u16> {
+    let pc = read_pc(sim_vm, user);
+    if pc == self.last_pc {
+      return None;
+    }
+    self.last_pc = pc;
+
+    let mut stop = None;
+    for bp in self.breakpoints.iter_mut().filter(|bp| bp.addr == pc && !bp.is_plain()) {
+      let mut read = |addr| sim_vm.user_read(user, addr);
+      if !bp.hit(&mut read) {
+        continue;
+      }
+      let message = bp.trace_message(&mut read);
+      if message.is_none() {
+        stop = Some(pc);
+      }
+      sim_tx.send(SimOutput::BreakpointHit(user, pc, bp.hits, message)).unwrap();
+    }
+    stop
+  }
+}
+
+/// The words a load is about to read, as `(addr, len)`. Loads of literals
+/// from the code stream are not memory reads.
+fn load_range(sim_vm: &mut SimulationVM, user: u64, opcode: Opcode) -> Option<(u16, u16)> {
+  match opcode {
+    Opcode::LoadInc(src, _, opt) |
+    Opcode::GatherInc(src, _, opt) if RegIndex::from(src as u8) != RegIndex::Ri => {
+      let addr = sim_vm.user_read(user, src as u16 * 4);
+      Some((addr, (opt as u16 & 0b11) + 1))
+    }
+    _ => None,
+  }
+}
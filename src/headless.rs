@@ -0,0 +1,170 @@
+use meivm2::MEM_SHARED_SIZE_U;
+use serde_json::json;
+use std::fmt;
+use std::sync::mpsc;
+
+use crate::app::Cli;
+use crate::breakpoints::Breakpoint;
+use crate::trace::register_name;
+use crate::wavebin::*;
+use crate::{sim, SimCommand, SimOutput, StopReason};
+
+impl fmt::Display for StopReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      StopReason::Halted => write!(f, "halted"),
+      StopReason::Breakpoint(pc) => write!(f, "breakpoint at {:04x}", pc),
+      StopReason::Watchpoint(addr, access) => write!(f, "watchpoint on {:04x} ({})", addr, access),
+      StopReason::TickLimit => write!(f, "tick limit reached"),
+    }
+  }
+}
+
+impl StopReason {
+  fn exit_code(self) -> i32 {
+    match self {
+      StopReason::Halted => 0,
+      StopReason::Breakpoint(_) | StopReason::Watchpoint(..) => 2,
+      StopReason::TickLimit => 3,
+    }
+  }
+}
+
+/// Loads the input file, runs the active user for at most `args.ticks` ticks
+/// and prints a report. Returns the process exit code.
+pub fn run(args: &Cli) -> Result<i32, Box<dyn std::error::Error>> {
+  let (sim_channel_tx, sim_channel_rx) = mpsc::sync_channel(16);
+  let (sim_output_tx, sim_output_rx) = mpsc::channel();
+  std::thread::spawn(|| {
+    sim(sim_channel_rx, sim_output_tx)
+  });
+
+  sim_channel_tx.send(SimCommand::Reset)?;
+
+  if let Some(infile) = &args.infile {
+    match load_program(infile.to_str().unwrap()) {
+      Ok(bin) => {
+        for section in bin.sections {
+          sim_channel_tx.send(SimCommand::WriteAll(section.addr, section.data))?;
+        }
+      }
+      Err(err) => {
+        eprintln!("Failed to load file: {}", err);
+        return Ok(1);
+      }
+    }
+  }
+
+  if !args.breakpoints.is_empty() {
+    let breakpoints = args.breakpoints.iter().map(|&addr| Breakpoint::new(addr)).collect();
+    sim_channel_tx.send(SimCommand::Breakpoints(breakpoints))?;
+  }
+  if let Some(trace) = &args.trace {
+    sim_channel_tx.send(SimCommand::Trace(Some(trace.to_str().unwrap().to_string())))?;
+  }
+  sim_channel_tx.send(SimCommand::RunFor(args.ticks))?;
+
+  let mut memory = vec![0u16; MEM_SHARED_SIZE_U];
+  let mut stopped = None;
+  loop {
+    match sim_output_rx.recv()? {
+      SimOutput::MemoryValues(_user, addr, vals) => {
+        for (i, val) in vals.iter().enumerate() {
+          memory[addr as usize + i] = *val;
+        }
+        // The memory snapshot following the stop is the final state.
+        if stopped.is_some() {
+          break;
+        }
+      }
+      SimOutput::Stopped(_user, reason, ticks) => {
+        stopped = Some((reason, ticks));
+      }
+      SimOutput::Error(err) => {
+        eprintln!("Error: {}", err);
+        return Ok(1);
+      }
+      _ => (),
+    }
+  }
+  let (reason, ticks) = stopped.unwrap();
+
+  if args.trace.is_some() {
+    sim_channel_tx.send(SimCommand::Trace(None))?;
+    loop {
+      match sim_output_rx.recv()? {
+        SimOutput::TraceClosed(path, records) => {
+          eprintln!("Wrote {} trace records to {}", records, path);
+          break;
+        }
+        SimOutput::Error(err) => {
+          eprintln!("Error: {}", err);
+          return Ok(1);
+        }
+        _ => (),
+      }
+    }
+  }
+
+  if args.json {
+    print_json(args, &memory, reason, ticks);
+  } else {
+    print_text(args, &memory, reason, ticks);
+  }
+
+  Ok(reason.exit_code())
+}
+
+fn dump_range(memory: &[u16], addr: u16, len: u16) -> &[u16] {
+  let start = (addr as usize).min(memory.len());
+  let end = (start + len as usize).min(memory.len());
+  &memory[start..end]
+}
+
+fn print_text(args: &Cli, memory: &[u16], reason: StopReason, ticks: usize) {
+  println!("Stopped: {} after {} ticks", reason, ticks);
+  println!("PC: {:04x}", memory[0x3c] & 0x1fff);
+  println!("Registers:");
+  for reg in 0..16 {
+    let words = &memory[reg * 4..reg * 4 + 4];
+    println!("  {:>2}: {:04x} {:04x} {:04x} {:04x}", register_name(reg as u8), words[0], words[1], words[2], words[3]);
+  }
+  for &(addr, len) in args.dump.iter() {
+    println!("Memory {:04x}..{:04x}:", addr, addr as usize + len as usize);
+    for (i, chunk) in dump_range(memory, addr, len).chunks(4).enumerate() {
+      let words = chunk.iter().map(|w| format!("{:04x}", w)).collect::<Vec<_>>().join(" ");
+      println!("  {:04x}: {}", addr as usize + i * 4, words);
+    }
+  }
+}
+
+fn print_json(args: &Cli, memory: &[u16], reason: StopReason, ticks: usize) {
+  let (kind, breakpoint, watchpoint) = match reason {
+    StopReason::Halted => ("halted", None, None),
+    StopReason::Breakpoint(pc) => ("breakpoint", Some(pc), None),
+    StopReason::Watchpoint(addr, access) => ("watchpoint", None, Some(json!({
+      "addr": addr,
+      "access": access.to_string(),
+    }))),
+    StopReason::TickLimit => ("tick_limit", None, None),
+  };
+  let registers = (0..16)
+    .map(|reg| (register_name(reg as u8), json!(memory[reg * 4..reg * 4 + 4])))
+    .collect::<serde_json::Map<_, _>>();
+  let ranges = args.dump.iter()
+    .map(|&(addr, len)| json!({
+      "addr": addr,
+      "values": dump_range(memory, addr, len),
+    }))
+    .collect::<Vec<_>>();
+  let report = json!({
+    "reason": kind,
+    "breakpoint": breakpoint,
+    "watchpoint": watchpoint,
+    "ticks": ticks,
+    "pc": memory[0x3c] & 0x1fff,
+    "registers": registers,
+    "memory": ranges,
+  });
+  println!("{}", report);
+}
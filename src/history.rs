@@ -0,0 +1,94 @@
+use meivm2::SimulationVM;
+use std::collections::VecDeque;
+
+/// What one tick changed, enough to undo it.
+#[derive(Debug, Clone)]
+pub struct Step<D> {
+  pub sleep_for: u32,
+  pub defer: D,
+  /// Address and value before the tick for every word the tick wrote, including the PC.
+  pub writes: Vec<(u16, u16)>,
+}
+
+/// Ring buffer of the last `depth` ticks of the active user, for `rstep` and `rcontinue`.
+///
+/// The debugger diffs memory around each tick and hands over the writes.
+/// `D` is the processor's defer state, read and restored through the accessors
+/// given to `new`. Ship physics is not recorded.
+#[derive(Debug)]
+pub struct History<D> {
+  steps: VecDeque<Step<D>>,
+  depth: usize,
+  pending: Option<(u32, D)>,
+  /// Steps undone since execution last moved forward.
+  pub rewound: usize,
+  get_defer: fn(&mut SimulationVM, u64) -> D,
+  set_defer: fn(&mut SimulationVM, u64, D),
+}
+
+impl<D> History<D> {
+  pub fn new(get_defer: fn(&mut SimulationVM, u64) -> D, set_defer: fn(&mut SimulationVM, u64, D)) -> Self {
+    History {
+      steps: VecDeque::new(),
+      depth: 0,
+      pending: None,
+      rewound: 0,
+      get_defer,
+      set_defer,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.steps.len()
+  }
+
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  /// Zero turns recording off.
+  pub fn set_depth(&mut self, depth: usize) {
+    self.depth = depth;
+    while self.steps.len() > depth {
+      self.steps.pop_front();
+    }
+  }
+
+  pub fn clear(&mut self) {
+    self.steps.clear();
+    self.rewound = 0;
+  }
+
+  pub fn before_tick(&mut self, sim_vm: &mut SimulationVM, user: u64) {
+    if self.depth == 0 {
+      return;
+    }
+    let sleep_for = sim_vm.make_user(user).proc.sleep_for;
+    self.pending = Some((sleep_for, (self.get_defer)(sim_vm, user)));
+  }
+
+  /// `writes` holds the address, old and new value of every word the tick changed.
+  pub fn after_tick(&mut self, writes: &[(u16, u16, u16)]) {
+    let Some((sleep_for, defer)) = self.pending.take() else {
+      return;
+    };
+    let writes = writes.iter().map(|&(addr, old, _)| (addr, old)).collect();
+    if self.steps.len() == self.depth {
+      self.steps.pop_front();
+    }
+    self.steps.push_back(Step { sleep_for, defer, writes });
+    self.rewound = 0;
+  }
+
+  /// Undoes the most recent tick. Returns the addresses it had written.
+  pub fn undo(&mut self, sim_vm: &mut SimulationVM, user: u64) -> Option<Vec<u16>> {
+    let step = self.steps.pop_back()?;
+    for &(addr, old) in step.writes.iter().rev() {
+      sim_vm.user_write(user, addr, old);
+    }
+    sim_vm.make_user(user).proc.sleep_for = step.sleep_for;
+    (self.set_defer)(sim_vm, user, step.defer);
+    self.rewound += 1;
+    Some(step.writes.into_iter().map(|(addr, _)| addr).collect())
+  }
+}
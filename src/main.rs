@@ -7,7 +7,16 @@ extern crate slog_term;
 extern crate log;
 
 use app::*;
+use breakpoints::{Access, Breakpoint, WatchHit, Watchpoint};
+use debugger::Debugger;
+use history::History;
+use trace::TraceWriter;
+use profile::Profile;
+use coverage::Coverage;
+use snapshot::{Snapshot, UserSnapshot};
+use clap::Parser as _;
 use slog::Drain;
+use std::collections::BTreeSet;
 use std::fs::OpenOptions;
 use std::vec;
 
@@ -20,14 +29,44 @@ use std::sync::mpsc;
 use std::time::Duration;
 
 mod app;
+mod assembler;
+mod breakpoints;
+mod coverage;
+mod debugger;
+mod headless;
+mod history;
+mod map;
+mod snapshot;
+mod symbols;
+mod trace;
+mod tracediff;
 mod utils;
 mod wavebin;
 mod modules;
+mod profile;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 enum SimCommand {
   Run,
   Step,
+  /// Step over a branch by running to the instruction after it.
+  Next,
+  /// Run until the innermost recorded call returns.
+  Finish,
+  RunUntil(u16),
+  ReverseStep,
+  ReverseContinue,
+  HistoryDepth(usize),
+  /// Start recording executed instructions to a file, or stop with `None`.
+  Trace(Option<String>),
+  ProfileReset,
+  CoverageReset,
+  ProfileEnable(bool),
+  /// Send profiler snapshots to the UI while it shows them.
+  ShowProfile(bool),
+  /// Send the Users view's rows every pass while it is shown.
+  ShowUsers(bool),
+  CoverageEnable(bool),
   Halt,
   Reset,
   Restart,
@@ -40,8 +79,21 @@ enum SimCommand {
   // ReadAll(Vec<u16>),
   SetUser(u64),
   WriteCommand(String),
-  CodeCommand(String),
-  Breakpoints(Vec<u16>),
+  Breakpoints(Vec<Breakpoint>),
+  Watchpoints(Vec<Watchpoint>),
+  RunFor(usize),
+  /// Save the active user, or every user with `true`, to a snapshot file.
+  SnapshotSave(String, bool),
+  SnapshotLoad(String),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum StopReason {
+  Halted,
+  Breakpoint(u16),
+  /// A watched address and whether it was read or written.
+  Watchpoint(u16, Access),
+  TickLimit,
 }
 
 #[derive(Debug)]
@@ -52,6 +104,25 @@ enum SimOutput {
   Error(String),
   SimState(u64, SimStateUpdate),
   ShipState(u64, Ship),
+  /// Ships of scheduled processes without a known user ID.
+  OtherShips(Vec<Ship>),
+  Stopped(u64, StopReason, usize),
+  /// A conditional breakpoint or tracepoint fired: address, hit count and trace message.
+  BreakpointHit(u64, u16, u32, Option<String>),
+  WatchpointHit(u64, WatchHit),
+  /// Recorded history length and how many steps of it have been undone.
+  History(u64, usize, usize),
+  /// A trace file was closed: path and number of records.
+  TraceClosed(String, u64),
+  Profile(u64, Profile),
+  Coverage(u64, Coverage),
+  /// A snapshot file was written with these users, skipping this many
+  /// processes without a known user ID.
+  SnapshotSaved(String, Vec<u64>, usize),
+  /// A snapshot file was restored with these users.
+  SnapshotLoaded(String, Vec<u64>),
+  /// Every scheduled process, then known users that are not scheduled.
+  Users(Vec<UserStatus>),
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -70,12 +141,21 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
   info!("File logging started.");
 
+  let args = Cli::parse();
+  if let Some(CliCommand::TraceDiff { a, b, context }) = &args.command {
+    std::process::exit(tracediff::run(a, b, *context));
+  }
+  if args.headless {
+    let code = headless::run(&args)?;
+    std::process::exit(code);
+  }
+
   let terminal = ratatui::init();
   execute!(std::io::stdout(), event::EnableMouseCapture)?;
   // Capture any panics so we can restore the terminal gracefully.
   let _ = std::panic::catch_unwind(|| {
     let mut app = App::new();
-    app.run(terminal)
+    app.run(terminal, args)
   });
   execute!(std::io::stdout(), event::DisableMouseCapture)?;
   ratatui::restore();
@@ -88,7 +168,16 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
   let mut active_user: u64 = 0;
   let mut debug_mode: bool = false;
   let mut running: bool = false;
-  let mut breakpoints: Vec<u16> = Vec::new();
+  // Users created through the UI, so a snapshot can cover all of them.
+  let mut known_users: BTreeSet<u64> = BTreeSet::from([0]);
+  let mut debugger = Debugger::new(History::new(
+    |sim_vm, user| sim_vm.make_user(user).proc.defer.clone(),
+    |sim_vm, user, defer| sim_vm.make_user(user).proc.defer = defer,
+  ));
+  let mut show_profile = false;
+  let mut show_users = false;
+  // Users in the last Users update; a change is sent even while the view is hidden.
+  let mut listed_users: Vec<Option<u64>> = Vec::new();
   let mut tickrate = 64;
   let mut mem: Vec<u16> = vec![0; MEM_SHARED_SIZE_U];
   loop {
@@ -105,19 +194,104 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
               running = true;
               sim_vm.user_run(active_user);
               let sleep = sim_vm.user_new(active_user).proc.sleep_for;
-              sim_vm.tick(sleep.max(1) as usize);
+              debugger.step(&mut sim_vm, active_user, sleep.max(1) as usize, &sim_tx);
+            }
+            SimCommand::Next => {
+              running = true;
+              sim_vm.user_run(active_user);
+              if let Some(addr) = debugger.step_over_target(&mut sim_vm, active_user) {
+                debug_mode = false;
+                debugger.temp_breaks = vec![addr];
+              } else {
+                debug_mode = true;
+                let sleep = sim_vm.user_new(active_user).proc.sleep_for;
+                debugger.step(&mut sim_vm, active_user, sleep.max(1) as usize, &sim_tx);
+              }
+            }
+            SimCommand::Finish => {
+              if debugger.calls.is_empty() {
+                return Err("No call recorded to finish. Step into the subroutine first.".into());
+              }
+              debug_mode = false;
+              running = true;
+              sim_vm.user_run(active_user);
+              debugger.temp_breaks = debugger.calls.clone();
+            }
+            SimCommand::RunUntil(addr) => {
+              debug_mode = false;
+              running = true;
+              sim_vm.user_run(active_user);
+              debugger.temp_breaks = vec![addr];
+            }
+            SimCommand::ReverseStep => {
+              running = false;
+              debug_mode = true;
+              if !debugger.reverse_step(&mut sim_vm, active_user) {
+                return Err("No recorded history to step back through. Use `history <ticks>` to start recording.".into());
+              }
+            }
+            SimCommand::ReverseContinue => {
+              running = false;
+              debug_mode = true;
+              let undone = debugger.history.len();
+              let reason = debugger.reverse_continue(&mut sim_vm, active_user);
+              let undone = undone - debugger.history.len();
+              // Reaching the start of the history is reported as the tick limit.
+              sim_tx.send(SimOutput::Stopped(active_user, reason.unwrap_or(StopReason::TickLimit), undone))?;
+            }
+            SimCommand::HistoryDepth(depth) => {
+              debugger.history.set_depth(depth);
+            }
+            SimCommand::ProfileReset => {
+              debugger.profiler.reset();
+            }
+            SimCommand::CoverageReset => {
+              debugger.coverage.reset();
+            }
+            SimCommand::ProfileEnable(enabled) => {
+              debugger.profiler.enabled = enabled;
+              debugger.profiler.dirty = true;
+            }
+            SimCommand::ShowProfile(show) => {
+              show_profile = show;
+            }
+            SimCommand::ShowUsers(show) => {
+              show_users = show;
+            }
+            SimCommand::CoverageEnable(enabled) => {
+              debugger.coverage.enabled = enabled;
+              debugger.coverage.dirty = true;
+            }
+            SimCommand::Trace(path) => {
+              if let Some(trace) = debugger.trace.take() {
+                let path = trace.path.clone();
+                sim_tx.send(SimOutput::TraceClosed(path, trace.finish()?))?;
+              }
+              if let Some(path) = path {
+                debugger.trace = Some(TraceWriter::create(&path)?);
+                debugger.ticks = 0;
+              }
             }
             SimCommand::Halt => {
               running = false;
+              debugger.temp_breaks.clear();
               sim_vm.user_halt(active_user);
             }
             SimCommand::Reset => {
               debug_mode = false;
               running = false;
+              debugger.temp_breaks.clear();
+              debugger.calls.clear();
+              debugger.history.clear();
+              debugger.profiler.reset();
               sim_vm.user_reset(active_user);
             }
             SimCommand::Restart => {
               running = false;
+              debugger.temp_breaks.clear();
+              debugger.calls.clear();
+              debugger.history.clear();
+              debugger.profiler.reset();
               sim_vm.user_restart(active_user);
             }
             SimCommand::Debug(debug) => {
@@ -147,17 +321,13 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
               vm_write(vals, user.as_mut(), 0, 0);
               // write_from_input(&mut sim_vm, 0, &vals);
             }
-            SimCommand::CodeCommand(vals) => {
-              let vals = &mut vals.split_whitespace();
-              vals.next();
-              let user = &mut sim_vm.make_user(active_user);
-              vm_write(vals, user.as_mut(), 0, 0x40);
-              // write_from_input(&mut sim_vm, 0x40, &vals);
-            }
             SimCommand::Breakpoints(bps) => {
-              breakpoints = bps;
+              debugger.set_breakpoints(bps);
               let vmproc = &mut sim_vm.make_user(active_user).proc;
-              vmproc.breakpoints = breakpoints.clone().iter().map(|&x| (0u64, x)).collect();
+              vmproc.breakpoints = debugger.breakpoints.iter().filter(|bp| bp.is_plain()).map(|bp| (0u64, bp.addr)).collect();
+            }
+            SimCommand::Watchpoints(wps) => {
+              debugger.watchpoints = wps;
             }
             // SimCommand::ReadAll(vals) => {
             //   let mem = vals.iter().map(|addr| {
@@ -165,8 +335,73 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
             //   }).collect::<Vec<u16>>();
             //   sim_tx.send(SimOutput::MemoryValues(active_user, 0, mem))?;
             // }
+            SimCommand::RunFor(ticks) => {
+              running = false;
+              sim_vm.user_run(active_user);
+              let mut reason = StopReason::TickLimit;
+              let mut count = 0;
+              while count < ticks {
+                let stop = debugger.tick(&mut sim_vm, active_user, &sim_tx);
+                count += 1;
+                let (at_breakpoint, is_running) = match sim_vm.find_user(active_user) {
+                  Some(user) => (user.proc.current_breakpoint.is_some(), user.proc.is_running),
+                  None => (false, false),
+                };
+                if at_breakpoint {
+                  reason = StopReason::Breakpoint(sim_vm.user_read(active_user, 0x3c) & 0x1fff);
+                  break;
+                }
+                if let Some(stop) = stop {
+                  reason = stop;
+                  break;
+                }
+                if !is_running {
+                  reason = StopReason::Halted;
+                  break;
+                }
+              }
+              sim_tx.send(SimOutput::Stopped(active_user, reason, count))?;
+            }
+            SimCommand::SnapshotSave(path, all) => {
+              let (users, skipped) = if all {
+                let vm_users = vm_users(&mut sim_vm, &known_users);
+                let users: Vec<u64> = vm_users.iter().filter_map(|vm_user| vm_user.id).collect();
+                let skipped = vm_users.len() - users.len();
+                (users, skipped)
+              } else {
+                (vec![active_user], 0)
+              };
+              let mut snapshot = Snapshot::default();
+              for &user in users.iter() {
+                snapshot.users.push(UserSnapshot::capture(&mut sim_vm, user)?);
+              }
+              snapshot.save(&path)?;
+              sim_tx.send(SimOutput::SnapshotSaved(path, users, skipped))?;
+            }
+            SimCommand::SnapshotLoad(path) => {
+              let snapshot = Snapshot::load(&path)?;
+              for user in snapshot.users.iter() {
+                user.restore(&mut sim_vm);
+                known_users.insert(user.user);
+                if user.user == active_user {
+                  running = user.running;
+                  debug_mode = false;
+                  debugger.temp_breaks.clear();
+                  debugger.calls.clear();
+                  debugger.history.clear();
+                  debugger.profiler.reset();
+                  debugger.coverage.reset();
+                }
+              }
+              let users = snapshot.users.iter().map(|user| user.user).collect();
+              sim_tx.send(SimOutput::SnapshotLoaded(path, users))?;
+            }
             SimCommand::SetUser(user) => {
               active_user = user;
+              known_users.insert(user);
+              debugger.history.clear();
+              debugger.profiler.reset();
+              debugger.coverage.reset();
               sim_tx.send(SimOutput::ChangeUser(user))?;
             }
             SimCommand::Summon => {
@@ -177,6 +412,17 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
             mem[i] = sim_vm.user_read(active_user, i as u16);
           }
           sim_tx.send(SimOutput::MemoryValues(active_user, 0, mem.clone()))?;
+          sim_tx.send(SimOutput::History(active_user, debugger.history.len(), debugger.history.rewound))?;
+          if show_profile && debugger.profiler.dirty {
+            debugger.profiler.dirty = false;
+            sim_tx.send(SimOutput::Profile(active_user, debugger.profiler.snapshot()))?;
+          }
+          if debugger.coverage.dirty {
+            debugger.coverage.dirty = false;
+            sim_tx.send(SimOutput::Coverage(active_user, debugger.coverage.clone()))?;
+          }
+          // Commands may have written memory behind the history's back.
+          debugger.invalidate();
 
           // let halt_reason =
 
@@ -199,7 +445,22 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
       Err(mpsc::RecvTimeoutError::Disconnected) => return
     }
     if running && !debug_mode {
-      sim_vm.tick(tickrate);
+      if debugger.inspects_ticks() {
+        // Single ticks so the debugger sees every instruction.
+        for _ in 0..tickrate {
+          if debugger.tick(&mut sim_vm, active_user, &sim_tx).is_some() {
+            debug_mode = true;
+            break;
+          }
+          if sim_vm.find_user(active_user).is_some_and(|user| user.proc.current_breakpoint.is_some()) {
+            break;
+          }
+        }
+      } else {
+        // Calls made during a batched run are not seen, so the stack would be stale.
+        debugger.calls.clear();
+        sim_vm.tick(tickrate);
+      }
       if let Some(&proc) = sim_vm.processes.front() {
         // Get the current breakpoint if any
         let proc = unsafe { &*proc };
@@ -225,6 +486,15 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
       }
 
       sim_tx.send(SimOutput::MemoryValues(active_user, 0, mem.clone())).unwrap();
+      sim_tx.send(SimOutput::History(active_user, debugger.history.len(), debugger.history.rewound)).unwrap();
+      if show_profile && debugger.profiler.dirty {
+        debugger.profiler.dirty = false;
+        sim_tx.send(SimOutput::Profile(active_user, debugger.profiler.snapshot())).unwrap();
+      }
+      if debugger.coverage.dirty {
+        debugger.coverage.dirty = false;
+        sim_tx.send(SimOutput::Coverage(active_user, debugger.coverage.clone())).unwrap();
+      }
       if let Some(user) = sim_vm.find_user(active_user) {
         sim_tx.send(SimOutput::SimState(active_user, SimStateUpdate {
           running: user.proc.is_running,
@@ -236,18 +506,93 @@ fn sim(sim_rx: mpsc::Receiver<SimCommand>, sim_tx: mpsc::Sender<SimOutput>) {
         // user.context.halt_reason = None;
       }
     }
-    let user = sim_vm.make_user(active_user);
-    let ship = Ship {
-      flight: FlightModule {
-        ..user.ship.flight
-      },
-      nav: NavModule {
-        ..user.ship.nav
-      },
-      phy: PhysicsEntity {
-        ..user.ship.phy
+    sim_vm.make_user(active_user);
+    let vm_users = vm_users(&mut sim_vm, &known_users);
+    let ids = vm_users.iter().map(|vm_user| vm_user.id).collect::<Vec<_>>();
+    if show_users || ids != listed_users {
+      listed_users = ids;
+      let users = user_statuses(&mut sim_vm, &vm_users, active_user, debug_mode);
+      sim_tx.send(SimOutput::Users(users)).unwrap();
+    }
+    let mut other_ships = Vec::new();
+    for vm_user in vm_users {
+      // The pointer came from the VM's process list or user table just above.
+      let user = unsafe { &*vm_user.user };
+      let ship = Ship {
+        flight: FlightModule {
+          ..user.ship.flight
+        },
+        nav: NavModule {
+          ..user.ship.nav
+        },
+        phy: PhysicsEntity {
+          ..user.ship.phy
+        }
+      };
+      match vm_user.id {
+        Some(id) => sim_tx.send(SimOutput::ShipState(id, ship)).unwrap(),
+        None => other_ships.push(ship),
+      }
+    }
+    sim_tx.send(SimOutput::OtherShips(other_ships)).unwrap();
+  }
+}
+
+/// A user the VM holds: a scheduled process or a known user that is not scheduled.
+struct VmUser {
+  /// None for processes the UI never created, as the VM does not expose
+  /// which ID a process belongs to.
+  id: Option<u64>,
+  user: *const meivm2::User,
+  scheduled: bool,
+}
+
+/// Every scheduled process in scheduling order, then the known users that
+/// are not scheduled. Processes get their ID by matching a known user.
+fn vm_users(sim_vm: &mut SimulationVM, known_users: &BTreeSet<u64>) -> Vec<VmUser> {
+  let mut known = Vec::new();
+  for &id in known_users.iter() {
+    if let Some(user) = sim_vm.find_user(id) {
+      known.push((id, user.as_ref() as *const meivm2::User));
+    }
+  }
+  let mut users: Vec<VmUser> = sim_vm.processes.iter().map(|&proc| VmUser {
+    id: known.iter().find(|&&(_, user)| std::ptr::eq(proc, user)).map(|&(id, _)| id),
+    user: proc,
+    scheduled: true,
+  }).collect();
+  for (id, user) in known {
+    if !users.iter().any(|vm_user| vm_user.id == Some(id)) {
+      users.push(VmUser { id: Some(id), user, scheduled: false });
+    }
+  }
+  users
+}
+
+fn user_statuses(sim_vm: &mut SimulationVM, vm_users: &[VmUser], active_user: u64, debug_mode: bool) -> Vec<UserStatus> {
+  let mut users = Vec::new();
+  for vm_user in vm_users {
+    let (pc, word) = match vm_user.id {
+      Some(id) => {
+        let pc = sim_vm.user_read(id, 0x3c) & 0x1fff;
+        (pc, sim_vm.user_read(id, pc))
       }
+      None => (0, 0),
+    };
+    // The pointer came from the VM's process list or user table just above.
+    let user = unsafe { &*vm_user.user };
+    let state = if user.proc.current_breakpoint.is_some() {
+      UserState::Breakpoint
+    } else if !user.proc.is_running {
+      UserState::Halted
+    } else if vm_user.id == Some(active_user) && debug_mode {
+      UserState::Debug
+    } else {
+      UserState::Running
     };
-    sim_tx.send(SimOutput::ShipState(active_user, ship)).unwrap();
+    let sleep = user.proc.sleep_for;
+    let pos = (user.ship.phy.pos.x, user.ship.phy.pos.y);
+    users.push(UserStatus { user: vm_user.id, state, scheduled: vm_user.scheduled, pc, word, sleep, pos });
   }
+  users
 }
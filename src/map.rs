@@ -0,0 +1,131 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Size of the world ships move in, in screen units.
+pub const WORLD_WIDTH: f32 = 1920.0;
+pub const WORLD_HEIGHT: f32 = 1080.0;
+
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 64.0;
+
+/// Camera and trail state of the Map view.
+#[derive(Debug, Clone)]
+pub struct MapView {
+  /// World position at the middle of the view.
+  pub center: (f32, f32),
+  /// 1.0 fits the world's width in the view.
+  pub zoom: f32,
+  /// Positions kept per ship for its trail.
+  pub trail_len: usize,
+  /// How far ahead the predicted path reaches, in ticks.
+  pub predict_ticks: u32,
+  trails: BTreeMap<u64, VecDeque<(f32, f32)>>,
+}
+
+impl Default for MapView {
+  fn default() -> Self {
+    MapView {
+      center: (WORLD_WIDTH / 2.0, WORLD_HEIGHT / 2.0),
+      zoom: 1.0,
+      trail_len: 64,
+      predict_ticks: 60,
+      trails: BTreeMap::new(),
+    }
+  }
+}
+
+impl MapView {
+  pub fn zoom_by(&mut self, factor: f32) {
+    self.zoom = (self.zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+  }
+
+  /// Moves the view by a fraction of its visible width.
+  pub fn pan(&mut self, dx: f32, dy: f32) {
+    let width = WORLD_WIDTH / self.zoom;
+    self.center.0 += dx * width;
+    self.center.1 += dy * width;
+  }
+
+  /// Canvas bounds for a view of `cols` by `rows` cells. Cells are about twice
+  /// as tall as they are wide, and the canvas y axis points up, so y is negated
+  /// (see `canvas_point`).
+  pub fn bounds(&self, cols: u16, rows: u16) -> ([f64; 2], [f64; 2]) {
+    let width = WORLD_WIDTH / self.zoom;
+    let height = width * rows.max(1) as f32 * 2.0 / cols.max(1) as f32;
+    let (x, y) = self.center;
+    (
+      [(x - width / 2.0) as f64, (x + width / 2.0) as f64],
+      [(-y - height / 2.0) as f64, (-y + height / 2.0) as f64],
+    )
+  }
+
+  /// Distance between grid lines, a 1, 2 or 5 step giving about eight lines across.
+  pub fn grid_spacing(&self) -> f32 {
+    let raw = WORLD_WIDTH / self.zoom / 8.0;
+    let magnitude = 10f32.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0].into_iter().map(|step| step * magnitude).find(|&step| step >= raw).unwrap()
+  }
+
+  /// Adds a position to the ship's trail if it moved.
+  pub fn record(&mut self, user: u64, pos: (f32, f32)) {
+    let trail = self.trails.entry(user).or_default();
+    if trail.back() != Some(&pos) {
+      trail.push_back(pos);
+    }
+    while trail.len() > self.trail_len {
+      trail.pop_front();
+    }
+  }
+
+  /// Oldest position first.
+  pub fn trail(&self, user: u64) -> impl Iterator<Item = &(f32, f32)> {
+    self.trails.get(&user).into_iter().flatten()
+  }
+
+  pub fn retain(&mut self, keep: impl Fn(u64) -> bool) {
+    self.trails.retain(|&user, _| keep(user));
+  }
+}
+
+/// World position to canvas coordinates.
+pub fn canvas_point((x, y): (f32, f32)) -> (f64, f64) {
+  (x as f64, -y as f64)
+}
+
+/// Where a ship ends up after `ticks` at its current velocity.
+pub fn predict(pos: (f32, f32), vel: (f32, f32), ticks: u32) -> (f32, f32) {
+  (pos.0 + vel.0 * ticks as f32, pos.1 + vel.1 * ticks as f32)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bounds_follow_zoom_and_pan() {
+    let mut map = MapView::default();
+    let (x, y) = map.bounds(100, 25);
+    assert_eq!(x, [0.0, 1920.0]);
+    assert_eq!(y, [-540.0 - 480.0, -540.0 + 480.0]);
+    assert_eq!(map.grid_spacing(), 500.0);
+
+    map.zoom_by(4.0);
+    map.pan(0.5, 0.0);
+    assert_eq!(map.bounds(100, 25).0, [960.0, 1440.0]);
+    assert_eq!(map.grid_spacing(), 100.0);
+
+    map.zoom_by(1000.0);
+    assert_eq!(map.zoom, MAX_ZOOM);
+  }
+
+  #[test]
+  fn trails_keep_the_last_positions() {
+    let mut map = MapView { trail_len: 3, ..MapView::default() };
+    for pos in [(0.0, 0.0), (1.0, 0.0), (1.0, 0.0), (2.0, 0.0), (3.0, 0.0)] {
+      map.record(4, pos);
+    }
+    assert_eq!(map.trail(4).copied().collect::<Vec<_>>(), vec![(1.0, 0.0), (2.0, 0.0), (3.0, 0.0)]);
+    map.retain(|user| user != 4);
+    assert_eq!(map.trail(4).count(), 0);
+    assert_eq!(predict((10.0, 10.0), (1.5, -2.0), 10), (25.0, -10.0));
+  }
+}
@@ -1,7 +1,363 @@
 use ratatui::{style::{Color, Stylize as _}, text::Span};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
 
+use crate::breakpoints::Access;
 use crate::S;
+use crate::utils::color_from_value;
 
+/// A heading register as a fraction of a turn, clockwise from up.
+pub fn angle_turns(value: u16) -> f32 {
+  value as f32 / 65536.0
+}
+
+/// A register holding a two's complement value.
+pub fn signed(value: u16) -> i16 {
+  value as i16
+}
+
+/// A signed 8.8 fixed point register.
+pub fn fixed(value: u16) -> f32 {
+  value as i16 as f32 / 256.0
+}
+
+/// Inverse of `fixed`, saturating at the ends of the range.
+pub fn to_fixed(value: f32) -> u16 {
+  (value * 256.0).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16 as u16
+}
+
+/// Inverse of `angle_turns`, wrapping whole turns.
+pub fn to_angle(turns: f32) -> u16 {
+  (turns.rem_euclid(1.0) * 65536.0).round() as u32 as u16
+}
+
+/// A relative heading register, as a signed fraction of a turn in -0.5..0.5.
+pub fn relative_turns(value: u16) -> f32 {
+  value as i16 as f32 / 65536.0
+}
+
+/// How a register's raw word should be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueType {
+  /// Only the hex is meaningful.
+  Raw,
+  Unsigned,
+  Signed,
+  /// Signed fixed point with this many fractional bits.
+  Fixed(u8),
+  /// Fraction of a turn, shown in degrees; `signed` for -180..180.
+  Angle { signed: bool },
+  Rgb565,
+  /// Named bits, lowest first. Unnamed set bits are shown by number.
+  Flags(Vec<String>),
+  /// Word `word` of a `words`-long big-endian ID, shown once on the middle word.
+  Id { word: u8, words: u8 },
+}
+
+impl ValueType {
+  /// The decoded value of the word at `addr`, or `None` if there is nothing to add to the hex.
+  pub fn decode<'a>(&self, memory: &[u16], addr: usize) -> Option<Span<'a>> {
+    let value = memory[addr];
+    match self {
+      ValueType::Raw => None,
+      ValueType::Unsigned => Some(value.to_string().light_blue()),
+      ValueType::Signed => Some(signed(value).to_string().light_blue()),
+      ValueType::Fixed(bits) => Some(format!("{:.2}", value as i16 as f32 / (1u32 << bits) as f32).light_blue()),
+      ValueType::Angle { signed: false } => Some(format!("{:.1}°", angle_turns(value) * 360.0).light_green()),
+      ValueType::Angle { signed: true } => Some(format!("{:.1}°", relative_turns(value) * 360.0).light_green()),
+      ValueType::Rgb565 => Some(S!("███").fg(color_from_value(value))),
+      ValueType::Flags(names) => {
+        let set = (0..16)
+          .filter(|bit| value & (1 << bit) != 0)
+          .map(|bit| names.get(bit).filter(|name| !name.is_empty()).cloned().unwrap_or(format!("b{}", bit)))
+          .collect::<Vec<_>>();
+        Some(if set.is_empty() { S!("-").dark_gray() } else { set.join(" ").light_magenta() })
+      }
+      &ValueType::Id { word, words } => {
+        if word != words / 2 {
+          return None;
+        }
+        let start = addr.checked_sub(word as usize)?;
+        let id = memory.get(start..start + words as usize)?.iter().fold(0u64, |id, &w| id << 16 | w as u64);
+        Some(format!("{:0width$x}", id, width = words as usize * 4).light_yellow())
+      }
+    }
+  }
+}
+
+/// One signature distance/ID register group of a radar module.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RadarContact {
+  pub distance: u16,
+  pub id: u64,
+  /// The color `module_register_info` gives this group.
+  pub color: Color,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadarState {
+  pub scan_heading: u16,
+  pub last_heading: u16,
+  /// The first `count` contact groups, as reported by RNSR.
+  pub contacts: Vec<RadarContact>,
+  pub count: u16,
+}
+
+/// Decoded nav module registers. Positions are in screen units, headings in turns.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct NavState {
+  pub screen: (u16, u16),
+  pub target_screen: (u16, u16),
+  pub target_selector: u16,
+  pub target_id: u64,
+  pub target_rel: (i16, i16),
+  pub target_rel_vel: (f32, f32),
+  pub heading_toward: f32,
+  pub heading_away: f32,
+  pub rel_heading_toward: f32,
+  pub rel_heading_away: f32,
+}
+
+/// Decoded flight module registers. Velocities are 8.8 fixed point, headings in turns.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FlightState {
+  pub requested_vel: (f32, f32),
+  pub current_vel: (f32, f32),
+  pub requested_heading: f32,
+  pub heading: f32,
+  pub engine_flags: u16,
+  pub color: u16,
+  pub alpha_mode: u16,
+}
+
+/// Names of the flight registers the TUI writes, looked up in the module definition.
+impl FlightState {
+  pub const RRVX: &str = "RRVx";
+  pub const RRVY: &str = "RRVy";
+  pub const RH: &str = "RH";
+  pub const EEN: &str = "EEN";
+}
+
+/// Error loading a module definition file.
+#[derive(Debug)]
+pub enum ModuleDefError {
+  Io(std::io::Error),
+  Parse(toml::de::Error),
+  /// The module is well-formed TOML but does not make sense.
+  Invalid { module: String, reason: String },
+}
+
+impl fmt::Display for ModuleDefError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use ModuleDefError::*;
+    match self {
+      Io(err) => write!(f, "{}", err),
+      Parse(err) => write!(f, "{}", err.message()),
+      Invalid { module, reason } => write!(f, "Module {}: {}", module, reason),
+    }
+  }
+}
+
+impl std::error::Error for ModuleDefError {}
+
+impl From<std::io::Error> for ModuleDefError {
+  fn from(err: std::io::Error) -> Self {
+    ModuleDefError::Io(err)
+  }
+}
+
+impl From<toml::de::Error> for ModuleDefError {
+  fn from(err: toml::de::Error) -> Self {
+    ModuleDefError::Parse(err)
+  }
+}
+
+/// Color of unused offsets and the bracket rows of multi-word registers.
+const DIM: Color = Color::Rgb(64, 64, 64);
+
+/// `type` of a register in a definition file. Multi-word IDs are a single
+/// entry with `words`, split into one `ValueType::Id` per word on load.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ValueSpec {
+  #[default]
+  Raw,
+  Unsigned,
+  Signed,
+  Fixed(u8),
+  Angle,
+  SignedAngle,
+  Rgb565,
+  Flags(Vec<String>),
+  Id,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegisterSpec {
+  offset: u16,
+  name: String,
+  desc: String,
+  color: Option<String>,
+  name_color: Option<String>,
+  #[serde(default, rename = "type")]
+  value: ValueSpec,
+  words: Option<u8>,
+  access: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ModuleSpec {
+  name: String,
+  ids: Vec<(u16, u16)>,
+  #[serde(default)]
+  registers: Vec<RegisterSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DefinitionFile {
+  #[serde(default)]
+  module: Vec<ModuleSpec>,
+}
+
+/// One word of a module's registers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Register {
+  pub name: String,
+  pub desc: String,
+  pub name_color: Color,
+  pub color: Color,
+  pub value: ValueType,
+  pub access: Access,
+}
+
+/// Register layout of one kind of module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleDef {
+  pub name: String,
+  /// Inclusive ranges of the module IDs this layout applies to.
+  pub ids: Vec<(u16, u16)>,
+  /// Keyed by offset from the slot base.
+  pub registers: BTreeMap<u16, Register>,
+}
+
+impl ModuleDef {
+  pub fn matches(&self, id: u16) -> bool {
+    self.ids.iter().any(|&(first, last)| (first..=last).contains(&id))
+  }
+
+  /// Offset of the first word of the register called `name`.
+  pub fn offset(&self, name: &str) -> Option<u16> {
+    self.registers.iter().find(|(_, reg)| reg.name == name).map(|(&offset, _)| offset)
+  }
+
+  fn from_spec(spec: ModuleSpec) -> Result<Self, ModuleDefError> {
+    let invalid = |reason: String| ModuleDefError::Invalid { module: spec.name.clone(), reason };
+    if spec.ids.is_empty() {
+      return Err(invalid(S!("no module IDs")));
+    }
+    if let Some(&(first, last)) = spec.ids.iter().find(|(first, last)| first > last) {
+      return Err(invalid(format!("ID range {:04x}..{:04x} is empty", first, last)));
+    }
+    let parse_color = |text: &Option<String>, default: Color| match text {
+      Some(text) => Color::from_str(text).map_err(|_| invalid(format!("invalid color \"{}\"", text))),
+      None => Ok(default),
+    };
+
+    let mut registers = BTreeMap::new();
+    for reg in spec.registers.iter() {
+      let name_color = parse_color(&reg.name_color, Color::Rgb(128, 128, 128))?;
+      let color = parse_color(&reg.color, Color::Gray)?;
+      let access = match &reg.access {
+        Some(text) => Access::parse(text).ok_or_else(|| invalid(format!("{}: invalid access \"{}\"", reg.name, text)))?,
+        None => Access::ReadWrite,
+      };
+      let words = match (&reg.value, reg.words) {
+        (ValueSpec::Id, words) => words.unwrap_or(1).max(1),
+        (_, None | Some(1)) => 1,
+        (_, Some(_)) => return Err(invalid(format!("{}: only id registers can span several words", reg.name))),
+      };
+      if reg.offset >= 0x20 || reg.offset + words as u16 > 0x20 {
+        return Err(invalid(format!("{}: offset 0x{:02x} is outside the slot", reg.name, reg.offset)));
+      }
+      if let ValueSpec::Fixed(bits) = reg.value && bits > 15 {
+        return Err(invalid(format!("{}: fixed point needs fewer than 16 fraction bits", reg.name)));
+      }
+
+      // The middle word carries the name; the others bracket it.
+      let width = reg.desc.chars().count().max(2) - 2;
+      for word in 0..words {
+        let value = match &reg.value {
+          ValueSpec::Raw => ValueType::Raw,
+          ValueSpec::Unsigned => ValueType::Unsigned,
+          ValueSpec::Signed => ValueType::Signed,
+          &ValueSpec::Fixed(bits) => ValueType::Fixed(bits),
+          ValueSpec::Angle => ValueType::Angle { signed: false },
+          ValueSpec::SignedAngle => ValueType::Angle { signed: true },
+          ValueSpec::Rgb565 => ValueType::Rgb565,
+          ValueSpec::Flags(names) => ValueType::Flags(names.clone()),
+          ValueSpec::Id => ValueType::Id { word, words },
+        };
+        let register = if word == words / 2 {
+          Register { name: reg.name.clone(), desc: reg.desc.clone(), name_color, color, value, access }
+        } else {
+          let desc = match word {
+            0 => format!("╭{}╮", "─".repeat(width)),
+            _ if word == words - 1 => format!("╰{}╯", "─".repeat(width)),
+            _ => format!("│{}│", " ".repeat(width)),
+          };
+          Register { name: reg.name.clone(), desc, name_color: DIM, color: DIM, value, access }
+        };
+        if registers.insert(reg.offset + word as u16, register).is_some() {
+          return Err(invalid(format!("{}: offset 0x{:02x} is defined twice", reg.name, reg.offset + word as u16)));
+        }
+      }
+    }
+    Ok(ModuleDef { name: spec.name, ids: spec.ids, registers })
+  }
+}
+
+const BUILTIN_MODULES: &str = include_str!("modules.toml");
+
+/// Every known module layout: the built-in `modules.toml` plus any files
+/// loaded on top of it. Later definitions win when IDs or names overlap.
+#[derive(Debug, Clone)]
+pub struct ModuleDefs {
+  defs: Vec<ModuleDef>,
+}
+
+impl Default for ModuleDefs {
+  fn default() -> Self {
+    ModuleDefs { defs: ModuleDefs::parse(BUILTIN_MODULES).expect("built-in module definitions are valid") }
+  }
+}
+
+impl ModuleDefs {
+  pub fn parse(text: &str) -> Result<Vec<ModuleDef>, ModuleDefError> {
+    let file: DefinitionFile = toml::from_str(text)?;
+    file.module.into_iter().map(ModuleDef::from_spec).collect()
+  }
+
+  /// Adds the modules in a definition file and returns their names.
+  pub fn load(&mut self, path: &str) -> Result<Vec<String>, ModuleDefError> {
+    let defs = ModuleDefs::parse(&std::fs::read_to_string(path)?)?;
+    let names = defs.iter().map(|def| def.name.clone()).collect();
+    self.defs.extend(defs);
+    Ok(names)
+  }
+
+  pub fn by_id(&self, id: u16) -> Option<&ModuleDef> {
+    self.defs.iter().rev().find(|def| def.matches(id))
+  }
+
+  pub fn by_name(&self, name: &str) -> Option<&ModuleDef> {
+    self.defs.iter().rev().find(|def| def.name == name)
+  }
+}
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Module {
@@ -10,6 +366,8 @@ pub enum Module {
   Nav(u16),
   Radar(u16),
   ConstStore(u16),
+  /// A module the TUI has no decoder for, known only from a definition file: slot and ID.
+  Other(u16, u16),
 }
 
 impl Module {
@@ -24,206 +382,282 @@ impl Module {
     }
     Some(slot as usize)
   }
-  pub fn type_from_id(id: u16, slot: u16) -> Option<Self> {
-    match id {
-      0x0 => Some(Module::Control(slot)),
-      0x1000..=0x1007 => Some(Module::ConstStore(slot)),
-      0x4000 => Some(Module::Flight(slot)),
-      0x4040..=0x4047 => Some(Module::Radar(slot)),
-      0x4050..=0x4057 => Some(Module::Nav(slot)),
-      _ => None,
+  /// The modules in the eight slots, read from the module IDs at `0x318..0x320`.
+  pub fn installed(defs: &ModuleDefs, memory: &[u16]) -> [Option<Self>; 8] {
+    let mut modules = [None; 8];
+    for (slot, module) in modules.iter_mut().enumerate() {
+      *module = Module::type_from_id(defs, memory[0x318 + slot], slot as u16);
     }
+    modules
   }
-  pub fn module_register_info<'a>(self, addr: usize) -> Option<(Span<'a>, Span<'a>)> {
-    if (addr < 0x300) || (addr > 0x3ff) {
-      return None;
-    }
-    let addr = match self {
+  pub fn slot(self) -> u16 {
+    match self {
       Module::Control(slot) |
       Module::Flight(slot) |
       Module::Nav(slot) |
-      Module::Radar(slot) => {
-        addr - (0x300 + slot as usize * 0x20)
-      }
-      _ => addr
+      Module::Radar(slot) |
+      Module::ConstStore(slot) |
+      Module::Other(slot, _) => slot,
+    }
+  }
+  /// First address of the module's registers.
+  pub fn base(self) -> u16 {
+    0x300 + self.slot() * 0x20
+  }
+  /// Decodes a radar module's registers; `None` for other modules.
+  pub fn radar_state(self, defs: &ModuleDefs, memory: &[u16]) -> Option<RadarState> {
+    let Module::Radar(_) = self else {
+      return None;
     };
+    let base = self.base() as usize;
+    let reg = |offset: usize| memory[base + offset];
+    let count = reg(0x07);
+    // Six contacts of four words each: distance, then a three-word ID.
+    let contacts = (0x08..0x20)
+      .step_by(4)
+      .map(|offset| RadarContact {
+        distance: reg(offset),
+        id: (1..4).fold(0u64, |id, i| id << 16 | reg(offset + i) as u64),
+        color: self.register(defs, base + offset).map_or(Color::White, |reg| reg.color),
+      })
+      .take(count as usize)
+      .collect();
+    Some(RadarState { scan_heading: reg(0x04), last_heading: reg(0x06), contacts, count })
+  }
+  /// Decodes a nav module's registers, found by name in its definition;
+  /// `None` for other modules or if the definition lacks one of them.
+  pub fn nav_state(self, defs: &ModuleDefs, memory: &[u16]) -> Option<NavState> {
+    let Module::Nav(_) = self else {
+      return None;
+    };
+    let def = self.def(defs)?;
+    let base = self.base() as usize;
+    let reg = |name: &str| def.offset(name).map(|offset| memory[base + offset as usize]);
+    let id = |name: &str| def.offset(name).map(|offset| {
+      (0..3).fold(0u64, |id, i| id << 16 | memory[base + offset as usize + i] as u64)
+    });
+    Some(NavState {
+      screen: (reg("NASx")?, reg("NASy")?),
+      target_screen: (reg("NTSx")?, reg("NTSy")?),
+      target_selector: reg("NTGT")?,
+      target_id: id("NTGI")?,
+      target_rel: (signed(reg("NRDx")?), signed(reg("NRDy")?)),
+      target_rel_vel: (fixed(reg("NRVx")?), fixed(reg("NRVy")?)),
+      heading_toward: angle_turns(reg("NAHT")?),
+      heading_away: angle_turns(reg("NAHF")?),
+      rel_heading_toward: relative_turns(reg("NRHT")?),
+      rel_heading_away: relative_turns(reg("NRHF")?),
+    })
+  }
+  /// Decodes a flight module's registers, found by name in its definition;
+  /// `None` for other modules or if the definition lacks one of them.
+  pub fn flight_state(self, defs: &ModuleDefs, memory: &[u16]) -> Option<FlightState> {
+    let Module::Flight(_) = self else {
+      return None;
+    };
+    let def = self.def(defs)?;
+    let base = self.base() as usize;
+    let reg = |name: &str| def.offset(name).map(|offset| memory[base + offset as usize]);
+    Some(FlightState {
+      requested_vel: (fixed(reg(FlightState::RRVX)?), fixed(reg(FlightState::RRVY)?)),
+      current_vel: (fixed(reg("CRVx")?), fixed(reg("CRVy")?)),
+      requested_heading: angle_turns(reg(FlightState::RH)?),
+      heading: angle_turns(reg("CAH")?),
+      engine_flags: reg(FlightState::EEN)?,
+      color: reg("SHCC")?,
+      alpha_mode: reg("SHCM")?,
+    })
+  }
+  pub fn type_from_id(defs: &ModuleDefs, id: u16, slot: u16) -> Option<Self> {
+    let def = defs.by_id(id)?;
+    match def.name.as_str() {
+      "Control" => Some(Module::Control(slot)),
+      "ConstStore" => Some(Module::ConstStore(slot)),
+      "Flight" => Some(Module::Flight(slot)),
+      "Radar" => Some(Module::Radar(slot)),
+      "Nav" => Some(Module::Nav(slot)),
+      _ => Some(Module::Other(slot, id)),
+    }
+  }
+  /// The definition giving this module's register layout.
+  pub fn def(self, defs: &ModuleDefs) -> Option<&ModuleDef> {
     match self {
-      Module::Control(_) => {
-        match addr {
-          0x00 => Some((S!("CSTA").fg(Color::Rgb(128, 128, 128)), S!("Core Status").gray())),
-          0x01 => Some((S!("CID").fg(Color::Rgb(128, 128, 128)), S!("Core ID").gray())),
-          0x02 => Some((S!("CPRT").fg(Color::Rgb(128, 128, 128)), S!("Core Exception Register").gray())),
-
-          0x04 => Some((S!("CCRL").fg(Color::Rgb(128, 128, 128)), S!("Core Instruction Register").gray())),
-
-          0x05 => Some((S!("CUID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x06 => Some((S!("CUID").fg(Color::Rgb(128, 128, 128)), S!("Core User ID").gray())),
-          0x07 => Some((S!("CUID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x0c => Some((S!("TID").fg(Color::Rgb(128, 128, 128)), S!("Current Thread ID").gray())),
-          0x0d => Some((S!("TPRT").fg(Color::Rgb(128, 128, 128)), S!("Thread Protection").gray())),
-          0x10 => Some((S!("TBK0").fg(Color::Rgb(128, 128, 128)), S!("Bank Select 0").gray())),
-          0x14 => Some((S!("TBK1").fg(Color::Rgb(128, 128, 128)), S!("Bank Select 1").gray())),
-
-          0x18 => Some((S!("TMS1").fg(Color::Rgb(128, 128, 128)), S!("Module Select 1").gray())),
-          0x19 => Some((S!("CMS1").fg(Color::Rgb(128, 128, 128)), S!("Module Select 1").gray())),
-          0x1a => Some((S!("CMS2").fg(Color::Rgb(128, 128, 128)), S!("Module Select 2").gray())),
-          0x1b => Some((S!("CMS3").fg(Color::Rgb(128, 128, 128)), S!("Module Select 3").gray())),
-          0x1c => Some((S!("CMS4").fg(Color::Rgb(128, 128, 128)), S!("Module Select 4").gray())),
-          0x1d => Some((S!("CMS5").fg(Color::Rgb(128, 128, 128)), S!("Module Select 5").gray())),
-          0x1e => Some((S!("CMS6").fg(Color::Rgb(128, 128, 128)), S!("Module Select 6").gray())),
-          0x1f => Some((S!("CMS7").fg(Color::Rgb(128, 128, 128)), S!("Module Select 7").gray())),
-          _ => Some((S!("").fg(Color::Rgb(64, 64, 64)), S!("0").fg(Color::Rgb(64, 64, 64)))),
-        }
-      }
-      Module::Flight(_) => {
-        match addr {
-          0x00 => Some((S!("MSTS").fg(Color::Rgb(128, 128, 128)), S!("Module Status").gray())),
-          0x01 => Some((S!("MMID").fg(Color::Rgb(128, 128, 128)), S!("Module ID").gray())),
-
-          0x04 => Some((S!("RRVx").fg(Color::Rgb(128, 128, 128)), S!("Req. Vx").gray())),
-          0x05 => Some((S!("RRVy").fg(Color::Rgb(128, 128, 128)), S!("Req. Vy").gray())),
-          0x06 => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x07 => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-
-          0x08 => Some((S!("CRVx").fg(Color::Rgb(128, 128, 128)), S!("Current Rel. Vx").gray())),
-          0x09 => Some((S!("CRVy").fg(Color::Rgb(128, 128, 128)), S!("Current Rel. Vy").gray())),
-
-          0x0c => Some((S!("RH").fg(Color::Rgb(128, 128, 128)), S!("Req. Heading").gray())),
-          0x0d => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x0e => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x0f => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-
-          0x10 => Some((S!("CAH").fg(Color::Rgb(128, 128, 128)), S!("Abs. Heading").gray())),
-
-          0x14 => Some((S!("EEN").fg(Color::Rgb(128, 128, 128)), S!("Engine Flags").gray())),
-          0x15 => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x16 => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x17 => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-
-          0x1c => Some((S!("SHCC").fg(Color::Rgb(128, 128, 128)), S!("Ship Color").gray())),
-          0x1d => Some((S!("SHCM").fg(Color::Rgb(128, 128, 128)), S!("Ship Alpha Mode").gray())),
-          _ => Some((S!("").fg(Color::Rgb(64, 64, 64)), S!("0").fg(Color::Rgb(64, 64, 64)))),
-        }
-      }
-      Module::Nav(_) => {
-        match addr {
-          0x00 => Some((S!("MSTS").fg(Color::Rgb(128, 128, 128)), S!("Module Status").gray())),
-          0x01 => Some((S!("MMID").fg(Color::Rgb(128, 128, 128)), S!("Module ID").gray())),
-
-          0x04 => Some((S!("NASx").fg(Color::Rgb(128, 128, 128)), S!("Abs. Screen X").gray())),
-          0x05 => Some((S!("NASy").fg(Color::Rgb(128, 128, 128)), S!("Abs. Screen Y").gray())),
+      Module::Control(_) => defs.by_name("Control"),
+      Module::Flight(_) => defs.by_name("Flight"),
+      Module::Nav(_) => defs.by_name("Nav"),
+      Module::Radar(_) => defs.by_name("Radar"),
+      Module::ConstStore(_) => defs.by_name("ConstStore"),
+      Module::Other(_, id) => defs.by_id(id),
+    }
+  }
+  /// Address of the register called `name` in this module's slot.
+  pub fn register_addr(self, defs: &ModuleDefs, name: &str) -> Option<u16> {
+    Some(self.base() + self.def(defs)?.offset(name)?)
+  }
+  /// The register at `addr`, or `None` if it is not in this module's slot or has no definition.
+  pub fn register(self, defs: &ModuleDefs, addr: usize) -> Option<&Register> {
+    let offset = addr.checked_sub(self.base() as usize).filter(|&offset| offset < 0x20)?;
+    self.def(defs)?.registers.get(&(offset as u16))
+  }
+  pub fn module_register_info<'a>(self, defs: &ModuleDefs, addr: usize) -> Option<(Span<'a>, Span<'a>, ValueType)> {
+    if (addr < 0x300) || (addr > 0x3ff) {
+      return None;
+    }
+    self.def(defs)?;
+    match self.register(defs, addr) {
+      Some(reg) => Some((reg.name.clone().fg(reg.name_color), reg.desc.clone().fg(reg.color), reg.value.clone())),
+      None => Some((S!("").fg(DIM), S!("0").fg(DIM), ValueType::Raw)),
+    }
+  }
+}
 
-          0x08 => Some((S!("NTSx").fg(Color::Rgb(128, 128, 128)), S!("Target Abs. X").gray())),
-          0x09 => Some((S!("NTSy").fg(Color::Rgb(128, 128, 128)), S!("Target Abs. Y").gray())),
-          0x0a => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
-          0x0b => Some((S!("M").fg(Color::Rgb(128, 128, 128)), S!("Scratch").blue())),
+#[cfg(test)]
+mod tests {
+  use super::*;
 
-          0x0c => Some((S!("NTGT").fg(Color::Rgb(128, 128, 128)), S!("Target Selector").gray())),
+  /// Installs the module with `id` in `slot`, returning the slot's base address.
+  fn install(memory: &mut [u16], slot: usize, id: u16) -> usize {
+    memory[0x318 + slot] = id;
+    0x300 + slot * 0x20
+  }
 
-          0x0d => Some((S!("NTGI").fg(Color::Rgb(64, 64, 64)),    S!("╭───────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x0e => Some((S!("NTGI").fg(Color::Rgb(128, 128, 128)), S!("Target ID").gray())),
-          0x0f => Some((S!("NTGI").fg(Color::Rgb(64, 64, 64)),    S!("╰───────╯").fg(Color::Rgb(64, 64, 64)))),
+  #[test]
+  fn decodes_radar_contacts() {
+    let defs = ModuleDefs::default();
+    let mut memory = vec![0u16; 0x2000];
+    let base = install(&mut memory, 2, 0x4040);
+    memory[base + 0x04] = 0x4000;
+    memory[base + 0x06] = 0x3f00;
+    memory[base + 0x07] = 2;
+    memory[base + 0x08] = 120;
+    memory[base + 0x09..base + 0x0c].copy_from_slice(&[0, 0, 7]);
+    memory[base + 0x0c] = 300;
+    memory[base + 0x0d..base + 0x10].copy_from_slice(&[1, 0, 2]);
+    memory[base + 0x10] = 999;
+
+    let radar = Module::installed(&defs, &memory)[2].unwrap();
+    assert_eq!(radar, Module::Radar(2));
+    assert_eq!(Module::Nav(2).radar_state(&defs, &memory), None);
+    let state = radar.radar_state(&defs, &memory).unwrap();
+    assert_eq!((state.scan_heading, state.last_heading, state.count), (0x4000, 0x3f00, 2));
+    assert_eq!(state.contacts.len(), 2);
+    assert_eq!((state.contacts[0].distance, state.contacts[0].id), (120, 7));
+    assert_eq!((state.contacts[1].distance, state.contacts[1].id), (300, 1 << 32 | 2));
+    assert_eq!(state.contacts[0].color, Color::Rgb(220, 0, 0));
+    assert_eq!(angle_turns(state.scan_heading), 0.25);
+  }
 
-          0x10 => Some((S!("NRDx").fg(Color::Rgb(128, 128, 128)), S!("Target Rel. X").gray())),
-          0x11 => Some((S!("NRDy").fg(Color::Rgb(128, 128, 128)), S!("Target Rel. Y").gray())),
+  #[test]
+  fn decodes_nav_registers() {
+    let defs = ModuleDefs::default();
+    let mut memory = vec![0u16; 0x2000];
+    let base = install(&mut memory, 3, 0x4050);
+    memory[base + 0x04] = 960;
+    memory[base + 0x0c] = 1;
+    memory[base + 0x0f] = 5;
+    memory[base + 0x10] = (-40i16) as u16;
+    memory[base + 0x11] = 25;
+    memory[base + 0x14] = 0x0180;
+    memory[base + 0x15] = 0xff00;
+    memory[base + 0x18] = 0xc000;
+    memory[base + 0x1c] = 0xe000;
+
+    let nav = Module::installed(&defs, &memory)[3].unwrap().nav_state(&defs, &memory).unwrap();
+    assert_eq!(nav.screen, (960, 0));
+    assert_eq!((nav.target_selector, nav.target_id), (1, 5));
+    assert_eq!(nav.target_rel, (-40, 25));
+    assert_eq!(nav.target_rel_vel, (1.5, -1.0));
+    assert_eq!((nav.heading_toward, nav.rel_heading_toward), (0.75, -0.125));
+    assert_eq!(Module::Radar(3).nav_state(&defs, &memory), None);
+  }
 
-          0x14 => Some((S!("NRVx").fg(Color::Rgb(128, 128, 128)), S!("Target Rel. Vx").gray())),
-          0x15 => Some((S!("NRVy").fg(Color::Rgb(128, 128, 128)), S!("Target Rel. Vy").gray())),
+  #[test]
+  fn decodes_typed_registers() {
+    let defs = ModuleDefs::default();
+    let mut memory = vec![0u16; 0x2000];
+    let flight = install(&mut memory, 1, 0x4000);
+    let nav = install(&mut memory, 2, 0x4050);
+    memory[flight + 0x04] = 0xff80;
+    memory[flight + 0x0c] = 0x4000;
+    memory[flight + 0x14] = 0b1001;
+    memory[nav + 0x0d..nav + 0x10].copy_from_slice(&[0x0001, 0x0203, 0x0405]);
+    memory[nav + 0x10] = 0xfffe;
+    memory[nav + 0x1c] = 0xc000;
+
+    let decode = |module: Module, addr: usize| {
+      let (_, _, kind) = module.module_register_info(&defs, addr).unwrap();
+      kind.decode(&memory, addr).map(|span| span.content.into_owned())
+    };
+    assert_eq!(decode(Module::Flight(1), flight + 0x04).as_deref(), Some("-0.50"));
+    assert_eq!(decode(Module::Flight(1), flight + 0x0c).as_deref(), Some("90.0°"));
+    assert_eq!(decode(Module::Flight(1), flight + 0x14).as_deref(), Some("b0 b3"));
+    assert_eq!(decode(Module::Flight(1), flight + 0x06), None);
+    assert_eq!(decode(Module::Nav(2), nav + 0x0d), None);
+    assert_eq!(decode(Module::Nav(2), nav + 0x0e).as_deref(), Some("000102030405"));
+    assert_eq!(decode(Module::Nav(2), nav + 0x10).as_deref(), Some("-2"));
+    assert_eq!(decode(Module::Nav(2), nav + 0x1c).as_deref(), Some("-90.0°"));
+    let names = ValueType::Flags(vec![S!("on"), S!("")]);
+    assert_eq!(names.decode(&[0b11], 0).unwrap().content, "on b1");
+  }
 
-          0x18 => Some((S!("NAHT").fg(Color::Rgb(128, 128, 128)), S!("T.Abs. Heading Toward").gray())),
-          0x1a => Some((S!("NAHF").fg(Color::Rgb(128, 128, 128)), S!("T.Abs. Heading Away").gray())),
+  #[test]
+  fn flight_values_round_trip() {
+    let defs = ModuleDefs::default();
+    let mut memory = vec![0u16; 0x2000];
+    install(&mut memory, 1, 0x4000);
+    let module = Module::installed(&defs, &memory)[1].unwrap();
+    let addr = |name: &str| module.register_addr(&defs, name).unwrap() as usize;
+    assert_eq!((addr(FlightState::RRVX), addr(FlightState::EEN)), (0x324, 0x334));
+    memory[addr(FlightState::RRVX)] = to_fixed(-2.5);
+    memory[addr(FlightState::RH)] = to_angle(1.25);
+    memory[addr(FlightState::EEN)] = 0b101;
+
+    let flight = module.flight_state(&defs, &memory).unwrap();
+    assert_eq!(flight.requested_vel, (-2.5, 0.0));
+    assert_eq!(flight.requested_heading, 0.25);
+    assert_eq!(flight.engine_flags, 0b101);
+    assert_eq!(to_fixed(1000.0), 0x7fff);
+    assert_eq!(to_angle(-0.25), 0xc000);
+  }
 
-          0x1c => Some((S!("NRHT").fg(Color::Rgb(128, 128, 128)), S!("T.Rel. Heading Toward").gray())),
-          0x1e => Some((S!("NRHF").fg(Color::Rgb(128, 128, 128)), S!("T.Rel. Heading Away").gray())),
-          _ => Some((S!("").fg(Color::Rgb(64, 64, 64)), S!("0").fg(Color::Rgb(64, 64, 64)))),
-        }
-      }
-      Module::Radar(_) => {
-        match addr {
-          0x00 => Some((S!("MSTS").fg(Color::Rgb(128, 128, 128)), S!("Module Status").gray())),
-          0x01 => Some((S!("MMID").fg(Color::Rgb(128, 128, 128)), S!("Module ID").gray())),
-
-          0x04 => Some((S!("RSSH").fg(Color::Rgb(128, 128, 128)), S!("Select Scan Heading").gray())),
-          0x06 => Some((S!("RHLS").fg(Color::Rgb(128, 128, 128)), S!("Last Scan Heading").gray())),
-          0x07 => Some((S!("RNSR").fg(Color::Rgb(128, 128, 128)), S!("Signature Count").gray())),
-
-          0x08 => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(220, 0, 0)))),
-          0x09 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x0a => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(220, 0, 0)))),
-          0x0b => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x0c => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(220, 160, 0)))),
-          0x0d => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x0e => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(220, 160, 0)))),
-          0x0f => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x10 => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(220, 220, 0)))),
-          0x11 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x12 => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(220, 220, 0)))),
-          0x13 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x14 => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(40, 220, 40)))),
-          0x15 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x16 => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(40, 220, 40)))),
-          0x17 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x18 => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(60, 80, 220)))),
-          0x19 => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x1a => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(60, 80, 220)))),
-          0x1b => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-
-          0x1c => Some((S!("RSDT").fg(Color::Rgb(128, 128, 128)), S!("Signature Distance").fg(Color::Rgb(140, 80, 200)))),
-          0x1d => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╭──────────╮").fg(Color::Rgb(64, 64, 64)))),
-          0x1e => Some((S!("RSID").fg(Color::Rgb(128, 128, 128)), S!("Signature ID").fg(Color::Rgb(140, 80, 200)))),
-          0x1f => Some((S!("RSID").fg(Color::Rgb(64, 64, 64)),    S!("╰──────────╯").fg(Color::Rgb(64, 64, 64)))),
-          _ => Some((S!("").fg(Color::Rgb(64, 64, 64)), S!("0").fg(Color::Rgb(64, 64, 64)))),
-        }
-      }
-      Module::ConstStore(_) => {
-        match addr {
-          0x00 => Some((S!("CS0x").fg(Color::Rgb(128, 128, 128)), S!("Constant c0.x").gray())),
-          0x01 => Some((S!("CS0y").fg(Color::Rgb(128, 128, 128)), S!("Constant c0.y").gray())),
-          0x02 => Some((S!("CS0z").fg(Color::Rgb(128, 128, 128)), S!("Constant c0.z").gray())),
-          0x03 => Some((S!("CS0w").fg(Color::Rgb(128, 128, 128)), S!("Constant c0.w").gray())),
-
-          0x04 => Some((S!("CS1x").fg(Color::Rgb(128, 128, 128)), S!("Constant c1.x").gray())),
-          0x05 => Some((S!("CS1y").fg(Color::Rgb(128, 128, 128)), S!("Constant c1.y").gray())),
-          0x06 => Some((S!("CS1z").fg(Color::Rgb(128, 128, 128)), S!("Constant c1.z").gray())),
-          0x07 => Some((S!("CS1w").fg(Color::Rgb(128, 128, 128)), S!("Constant c1.w").gray())),
-
-          0x08 => Some((S!("CS2x").fg(Color::Rgb(128, 128, 128)), S!("Constant c2.x").gray())),
-          0x09 => Some((S!("CS2y").fg(Color::Rgb(128, 128, 128)), S!("Constant c2.y").gray())),
-          0x0a => Some((S!("CS2z").fg(Color::Rgb(128, 128, 128)), S!("Constant c2.z").gray())),
-          0x0b => Some((S!("CS2w").fg(Color::Rgb(128, 128, 128)), S!("Constant c2.w").gray())),
-
-          0x0c => Some((S!("CS3x").fg(Color::Rgb(128, 128, 128)), S!("Constant c3.x").gray())),
-          0x0d => Some((S!("CS3y").fg(Color::Rgb(128, 128, 128)), S!("Constant c3.y").gray())),
-          0x0e => Some((S!("CS3z").fg(Color::Rgb(128, 128, 128)), S!("Constant c3.z").gray())),
-          0x0f => Some((S!("CS3w").fg(Color::Rgb(128, 128, 128)), S!("Constant c3.w").gray())),
-
-          0x10 => Some((S!("CS4x").fg(Color::Rgb(128, 128, 128)), S!("Constant c4.x").gray())),
-          0x11 => Some((S!("CS4y").fg(Color::Rgb(128, 128, 128)), S!("Constant c4.y").gray())),
-          0x12 => Some((S!("CS4z").fg(Color::Rgb(128, 128, 128)), S!("Constant c4.z").gray())),
-          0x13 => Some((S!("CS4w").fg(Color::Rgb(128, 128, 128)), S!("Constant c4.w").gray())),
-
-          0x14 => Some((S!("CS5x").fg(Color::Rgb(128, 128, 128)), S!("Constant c5.x").gray())),
-          0x15 => Some((S!("CS5y").fg(Color::Rgb(128, 128, 128)), S!("Constant c5.y").gray())),
-          0x16 => Some((S!("CS5z").fg(Color::Rgb(128, 128, 128)), S!("Constant c5.z").gray())),
-          0x17 => Some((S!("CS5w").fg(Color::Rgb(128, 128, 128)), S!("Constant c5.w").gray())),
-
-          0x18 => Some((S!("CS6x").fg(Color::Rgb(128, 128, 128)), S!("Constant c6.x").gray())),
-          0x19 => Some((S!("CS6y").fg(Color::Rgb(128, 128, 128)), S!("Constant c6.y").gray())),
-          0x1a => Some((S!("CS6z").fg(Color::Rgb(128, 128, 128)), S!("Constant c6.z").gray())),
-          0x1b => Some((S!("CS6w").fg(Color::Rgb(128, 128, 128)), S!("Constant c6.w").gray())),
-
-          0x1c => Some((S!("CS7x").fg(Color::Rgb(128, 128, 128)), S!("Constant c7.x").gray())),
-          0x1d => Some((S!("CS7y").fg(Color::Rgb(128, 128, 128)), S!("Constant c7.y").gray())),
-          0x1e => Some((S!("CS7z").fg(Color::Rgb(128, 128, 128)), S!("Constant c7.z").gray())),
-          0x1f => Some((S!("CS7w").fg(Color::Rgb(128, 128, 128)), S!("Constant c7.w").gray())),
-          _ => Some((S!("").fg(Color::Rgb(64, 64, 64)), S!("0").fg(Color::Rgb(64, 64, 64)))),
-        }
-      }
-    }
+  #[test]
+  fn loads_user_definitions() {
+    let mut defs = ModuleDefs::default();
+    let added = ModuleDefs::parse(r##"
+      [[module]]
+      name = "Shield"
+      ids = [[0x4060, 0x4061]]
+      registers = [
+        { offset = 0x04, name = "SLVL", desc = "Shield Level", type = { fixed = 4 }, color = "#00c8c8" },
+        { offset = 0x08, name = "SSRC", desc = "Source", type = "id", words = 3, access = "r" },
+      ]
+    "##).unwrap();
+    defs.defs.extend(added);
+
+    let mut memory = vec![0u16; 0x2000];
+    let base = install(&mut memory, 4, 0x4061);
+    install(&mut memory, 5, 0x4040);
+    memory[base + 0x04] = 0x0028;
+    let modules = Module::installed(&defs, &memory);
+    let shield = modules[4].unwrap();
+    assert_eq!(shield, Module::Other(4, 0x4061));
+    assert_eq!(modules[5], Some(Module::Radar(5)));
+
+    let (name, desc, kind) = shield.module_register_info(&defs, 0x384).unwrap();
+    assert_eq!((name.content.as_ref(), desc.style.fg), ("SLVL", Some(Color::Rgb(0, 200, 200))));
+    assert_eq!(kind.decode(&memory, 0x384).unwrap().content, "2.50");
+    let bracket = shield.register(&defs, 0x38a).unwrap();
+    assert_eq!((bracket.desc.as_str(), bracket.access), ("╰────╯", Access::Read));
+    assert_eq!(shield.register(&defs, 0x389).unwrap().value, ValueType::Id { word: 1, words: 3 });
+    assert_eq!(shield.module_register_info(&defs, 0x385).unwrap().1.content, "0");
+    assert_eq!(Module::type_from_id(&defs, 0x4062, 0), None);
+
+    let invalid = |text: &str| matches!(ModuleDefs::parse(text), Err(ModuleDefError::Invalid { .. }));
+    assert!(invalid("[[module]]\nname = \"A\"\nids = []"));
+    assert!(invalid("[[module]]\nname = \"A\"\nids = [[1, 1]]\nregisters = [{ offset = 0x1f, name = \"X\", desc = \"\", type = \"id\", words = 2 }]"));
+    assert!(invalid("[[module]]\nname = \"A\"\nids = [[1, 1]]\nregisters = [{ offset = 0, name = \"X\", desc = \"\", color = \"mauve\" }]"));
+    assert!(invalid("[[module]]\nname = \"A\"\nids = [[1, 1]]\nregisters = [{ offset = 0xffff, name = \"X\", desc = \"\", type = \"id\", words = 2 }]"));
+    assert!(invalid("[[module]]\nname = \"A\"\nids = [[1, 1]]\nregisters = [{ offset = 0, name = \"X\", desc = \"\", type = { fixed = 16 } }]"));
+    assert!(matches!(ModuleDefs::parse("[[module]]\nname = 1"), Err(ModuleDefError::Parse(_))));
   }
 }
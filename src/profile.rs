@@ -0,0 +1,125 @@
+use meivm2::MEM_SHARED_SIZE_U;
+use meivm2::opcode::Opcode;
+use std::collections::HashMap;
+
+/// Name of an opcode's variant, e.g. `LoadInc`, used to group the profile by kind.
+pub fn opcode_kind(opcode: Opcode) -> String {
+  let name = format!("{:?}", opcode);
+  let end = name.find(|c: char| !c.is_alphanumeric()).unwrap_or(name.len());
+  name[..end].to_string()
+}
+
+/// Tick counts for one address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct HotSpot {
+  pub addr: u16,
+  pub executed: u64,
+  /// Ticks spent sleeping with the PC at this address.
+  pub sleeping: u64,
+}
+
+/// Snapshot of the profiler sent to the UI, with only the addresses that were hit.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+  /// Sorted by address.
+  pub spots: Vec<HotSpot>,
+  /// Executed instructions per opcode kind, most frequent first.
+  pub kinds: Vec<(String, u64)>,
+  pub executed: u64,
+  pub sleeping: u64,
+  pub enabled: bool,
+}
+
+impl Profile {
+  pub fn at(&self, addr: u16) -> Option<&HotSpot> {
+    self.spots.binary_search_by_key(&addr, |spot| spot.addr).ok().map(|i| &self.spots[i])
+  }
+
+  pub fn hottest(&self) -> u64 {
+    self.spots.iter().map(|spot| spot.executed).max().unwrap_or(0)
+  }
+}
+
+/// Counts executed and sleeping ticks per address in the `sim` thread.
+#[derive(Debug)]
+pub struct Profiler {
+  executed: Vec<u64>,
+  sleeping: Vec<u64>,
+  kinds: HashMap<String, u64>,
+  /// Off by default, as recording needs the sim to run one tick at a time.
+  pub enabled: bool,
+  /// Something was recorded since the last snapshot.
+  pub dirty: bool,
+}
+
+impl Default for Profiler {
+  fn default() -> Self {
+    Profiler {
+      executed: vec![0; MEM_SHARED_SIZE_U],
+      sleeping: vec![0; MEM_SHARED_SIZE_U],
+      kinds: HashMap::new(),
+      enabled: false,
+      dirty: false,
+    }
+  }
+}
+
+impl Profiler {
+  pub fn record(&mut self, pc: u16, opcode: Opcode, sleeping: bool) {
+    let addr = pc as usize % MEM_SHARED_SIZE_U;
+    if sleeping {
+      self.sleeping[addr] += 1;
+    } else {
+      self.executed[addr] += 1;
+      *self.kinds.entry(opcode_kind(opcode)).or_default() += 1;
+    }
+    self.dirty = true;
+  }
+
+  pub fn reset(&mut self) {
+    *self = Profiler { enabled: self.enabled, dirty: true, ..Profiler::default() };
+  }
+
+  pub fn snapshot(&self) -> Profile {
+    let spots = (0..MEM_SHARED_SIZE_U)
+      .filter(|&i| self.executed[i] > 0 || self.sleeping[i] > 0)
+      .map(|i| HotSpot { addr: i as u16, executed: self.executed[i], sleeping: self.sleeping[i] })
+      .collect::<Vec<_>>();
+    let mut kinds = self.kinds.iter().map(|(kind, &count)| (kind.clone(), count)).collect::<Vec<_>>();
+    kinds.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    Profile {
+      executed: spots.iter().map(|spot| spot.executed).sum(),
+      sleeping: spots.iter().map(|spot| spot.sleeping).sum(),
+      spots,
+      kinds,
+      enabled: self.enabled,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counts_ticks_per_address_and_kind() {
+    let mut profiler = Profiler { enabled: true, ..Profiler::default() };
+    let nop = Opcode::parse(0);
+    profiler.record(0x41, nop, false);
+    profiler.record(0x40, nop, false);
+    profiler.record(0x40, nop, false);
+    profiler.record(0x40, nop, true);
+
+    let profile = profiler.snapshot();
+    assert_eq!(profile.spots.len(), 2);
+    assert_eq!(profile.at(0x40), Some(&HotSpot { addr: 0x40, executed: 2, sleeping: 1 }));
+    assert_eq!(profile.at(0x42), None);
+    assert_eq!(profile.hottest(), 2);
+    assert_eq!((profile.executed, profile.sleeping), (3, 1));
+    assert_eq!(profile.kinds, vec![(opcode_kind(nop), 3)]);
+
+    profiler.reset();
+    assert!(profiler.snapshot().spots.is_empty());
+    assert!(profiler.enabled);
+  }
+}
@@ -0,0 +1,257 @@
+use meivm2::{MEM_SHARED_SIZE_U, SimulationVM};
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"WVsn";
+const VERSION: u8 = 2;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+  Io(std::io::Error),
+  BadMagic([u8; 4]),
+  UnsupportedVersion(u8),
+  /// The file ends in the middle of the user starting at byte `offset`.
+  Truncated { offset: usize },
+  UnknownUser(u64),
+}
+
+impl fmt::Display for SnapshotError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use SnapshotError::*;
+    match self {
+      Io(err) => write!(f, "{}", err),
+      BadMagic(magic) => write!(f, "Invalid snapshot magic number {:02x?}", magic),
+      UnsupportedVersion(version) => write!(f, "Unsupported snapshot version {}", version),
+      Truncated { offset } => write!(f, "Snapshot user at offset 0x{:x} is truncated", offset),
+      UnknownUser(user) => write!(f, "User {} does not exist", user),
+    }
+  }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+  fn from(err: std::io::Error) -> Self {
+    SnapshotError::Io(err)
+  }
+}
+
+/// Everything needed to put one user back where it was.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct UserSnapshot {
+  pub user: u64,
+  pub running: bool,
+  pub sleep_for: u32,
+  pub defer: Option<u16>,
+  pub breakpoints: Vec<(u64, u16)>,
+  pub pos: (f32, f32),
+  pub vel: (f32, f32),
+  pub heading: f32,
+  pub color: u16,
+  pub memory: Vec<u16>,
+}
+
+/// On disk (big-endian): `WVsn`, version u8, user count u16, then per user:
+/// id u64, flags u8 (1 running, 2 defer pending), sleep_for u32, defer u16,
+/// pos x/y f32, vel x/y f32, heading f32, color u16, breakpoint count u16 with
+/// (u64, u16) pairs, and the word count u16 followed by the user's memory.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Snapshot {
+  pub users: Vec<UserSnapshot>,
+}
+
+impl UserSnapshot {
+  pub fn capture(sim_vm: &mut SimulationVM, user: u64) -> Result<Self, SnapshotError> {
+    let memory = (0..MEM_SHARED_SIZE_U).map(|i| sim_vm.user_read(user, i as u16)).collect();
+    let vm_user = sim_vm.find_user(user).ok_or(SnapshotError::UnknownUser(user))?;
+    Ok(UserSnapshot {
+      user,
+      running: vm_user.proc.is_running,
+      sleep_for: vm_user.proc.sleep_for,
+      defer: vm_user.proc.defer,
+      breakpoints: vm_user.proc.breakpoints.clone(),
+      pos: (vm_user.ship.phy.pos.x, vm_user.ship.phy.pos.y),
+      vel: (vm_user.ship.phy.vel.x, vm_user.ship.phy.vel.y),
+      heading: vm_user.ship.phy.heading,
+      color: vm_user.ship.flight.color,
+      memory,
+    })
+  }
+
+  /// Creates the user if needed and restores it.
+  pub fn restore(&self, sim_vm: &mut SimulationVM) {
+    sim_vm.make_user(self.user);
+    for (i, &word) in self.memory.iter().enumerate() {
+      sim_vm.user_write(self.user, i as u16, word);
+    }
+    if self.running {
+      sim_vm.user_run(self.user);
+    } else {
+      sim_vm.user_halt(self.user);
+    }
+    let vm_user = sim_vm.make_user(self.user);
+    vm_user.proc.sleep_for = self.sleep_for;
+    vm_user.proc.defer = self.defer;
+    vm_user.proc.breakpoints = self.breakpoints.clone();
+    vm_user.ship.phy.pos.x = self.pos.0;
+    vm_user.ship.phy.pos.y = self.pos.1;
+    vm_user.ship.phy.vel.x = self.vel.0;
+    vm_user.ship.phy.vel.y = self.vel.1;
+    vm_user.ship.phy.heading = self.heading;
+    vm_user.ship.flight.color = self.color;
+  }
+
+  fn encode(&self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.user.to_be_bytes());
+    out.push(self.running as u8 | (self.defer.is_some() as u8) << 1);
+    out.extend_from_slice(&self.sleep_for.to_be_bytes());
+    out.extend_from_slice(&self.defer.unwrap_or(0).to_be_bytes());
+    out.extend_from_slice(&self.pos.0.to_be_bytes());
+    out.extend_from_slice(&self.pos.1.to_be_bytes());
+    out.extend_from_slice(&self.vel.0.to_be_bytes());
+    out.extend_from_slice(&self.vel.1.to_be_bytes());
+    out.extend_from_slice(&self.heading.to_be_bytes());
+    out.extend_from_slice(&self.color.to_be_bytes());
+    out.extend_from_slice(&(self.breakpoints.len() as u16).to_be_bytes());
+    for &(id, addr) in self.breakpoints.iter() {
+      out.extend_from_slice(&id.to_be_bytes());
+      out.extend_from_slice(&addr.to_be_bytes());
+    }
+    out.extend_from_slice(&(self.memory.len() as u16).to_be_bytes());
+    for word in self.memory.iter() {
+      out.extend_from_slice(&word.to_be_bytes());
+    }
+  }
+}
+
+impl Snapshot {
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut out = MAGIC.to_vec();
+    out.push(VERSION);
+    out.extend_from_slice(&(self.users.len() as u16).to_be_bytes());
+    for user in self.users.iter() {
+      user.encode(&mut out);
+    }
+    out
+  }
+
+  pub fn parse(buffer: &[u8]) -> Result<Self, SnapshotError> {
+    if buffer.len() < 7 {
+      return Err(SnapshotError::Truncated { offset: 0 });
+    }
+    let magic: [u8; 4] = buffer[0..4].try_into().unwrap();
+    if &magic != MAGIC {
+      return Err(SnapshotError::BadMagic(magic));
+    }
+    if buffer[4] != VERSION {
+      return Err(SnapshotError::UnsupportedVersion(buffer[4]));
+    }
+    let count = u16::from_be_bytes([buffer[5], buffer[6]]);
+
+    let mut users = Vec::new();
+    let mut pos = 7;
+    for _ in 0..count {
+      let start = pos;
+      let mut take = |n: usize| -> Result<&[u8], SnapshotError> {
+        let bytes = buffer.get(pos..pos + n).ok_or(SnapshotError::Truncated { offset: start })?;
+        pos += n;
+        Ok(bytes)
+      };
+      let user = u64::from_be_bytes(take(8)?.try_into().unwrap());
+      let flags = take(1)?[0];
+      let sleep_for = u32::from_be_bytes(take(4)?.try_into().unwrap());
+      let defer = u16::from_be_bytes(take(2)?.try_into().unwrap());
+      let x = f32::from_be_bytes(take(4)?.try_into().unwrap());
+      let y = f32::from_be_bytes(take(4)?.try_into().unwrap());
+      let vx = f32::from_be_bytes(take(4)?.try_into().unwrap());
+      let vy = f32::from_be_bytes(take(4)?.try_into().unwrap());
+      let heading = f32::from_be_bytes(take(4)?.try_into().unwrap());
+      let color = u16::from_be_bytes(take(2)?.try_into().unwrap());
+      let count = u16::from_be_bytes(take(2)?.try_into().unwrap());
+      let mut breakpoints = Vec::new();
+      for _ in 0..count {
+        let id = u64::from_be_bytes(take(8)?.try_into().unwrap());
+        let addr = u16::from_be_bytes(take(2)?.try_into().unwrap());
+        breakpoints.push((id, addr));
+      }
+      let len = u16::from_be_bytes(take(2)?.try_into().unwrap()) as usize;
+      let memory = take(len * 2)?
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+      users.push(UserSnapshot {
+        user,
+        running: flags & 1 != 0,
+        sleep_for,
+        defer: (flags & 2 != 0).then_some(defer),
+        breakpoints,
+        pos: (x, y),
+        vel: (vx, vy),
+        heading,
+        color,
+        memory,
+      });
+    }
+    Ok(Snapshot { users })
+  }
+
+  pub fn load(path: &str) -> Result<Self, SnapshotError> {
+    Snapshot::parse(&std::fs::read(path)?)
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), SnapshotError> {
+    std::fs::write(path, self.serialize())?;
+    Ok(())
+  }
+}
+
+/// "user 3" or "users 0, 1, 2" for status messages.
+pub fn describe_users(users: &[u64]) -> String {
+  let ids = users.iter().map(|user| user.to_string()).collect::<Vec<_>>().join(", ");
+  if users.len() == 1 { format!("user {}", ids) } else { format!("users {}", ids) }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn sample() -> Snapshot {
+    Snapshot {
+      users: vec![
+        UserSnapshot {
+          user: 0,
+          running: true,
+          sleep_for: 12,
+          defer: None,
+          breakpoints: vec![(0, 0x44), (0, 0x80)],
+          pos: (960.5, 540.25),
+          vel: (-1.5, 0.125),
+          heading: 0.75,
+          color: 0xf800,
+          memory: (0..0x2000).map(|i| i as u16 ^ 0x5a5a).collect(),
+        },
+        UserSnapshot { user: 7, defer: Some(0x1234), ..UserSnapshot::default() },
+      ],
+    }
+  }
+
+  #[test]
+  fn round_trips() {
+    let snapshot = sample();
+    let bytes = snapshot.serialize();
+    assert_eq!(&bytes[..7], b"WVsn\x02\x00\x02");
+    assert_eq!(Snapshot::parse(&bytes).unwrap(), snapshot);
+    assert_eq!(Snapshot::parse(&Snapshot::default().serialize()).unwrap(), Snapshot::default());
+  }
+
+  #[test]
+  fn rejects_bad_files() {
+    assert!(matches!(Snapshot::parse(b"WVsn"), Err(SnapshotError::Truncated { offset: 0 })));
+    assert!(matches!(Snapshot::parse(b"WVtr\x02\x00\x00"), Err(SnapshotError::BadMagic(_))));
+    assert!(matches!(Snapshot::parse(b"WVsn\x01\x00\x00"), Err(SnapshotError::UnsupportedVersion(1))));
+
+    let bytes = sample().serialize();
+    for len in [8, 40, 100, bytes.len() - 1] {
+      assert!(matches!(Snapshot::parse(&bytes[..len]), Err(SnapshotError::Truncated { .. })));
+    }
+  }
+}
@@ -0,0 +1,147 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Labels and source lines for a loaded program.
+///
+/// On disk this is a plain text sidecar (e.g. `prog.wvm.sym`) with one entry per line:
+///   `sym <addr> <name>`
+///   `src <addr> <line> <source text>`
+/// Addresses are hex, `;` at the start of a line is a comment.
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+  labels: BTreeMap<u16, String>,
+  addrs: HashMap<String, u16>,
+  source: BTreeMap<u16, (usize, String)>,
+}
+
+impl SymbolTable {
+  pub fn is_empty(&self) -> bool {
+    self.labels.is_empty() && self.source.is_empty()
+  }
+
+  pub fn len(&self) -> usize {
+    self.labels.len()
+  }
+
+  /// Names the address, dropping its previous label and any other address with this name.
+  pub fn add_label(&mut self, addr: u16, name: &str) {
+    if let Some(old) = self.labels.insert(addr, name.to_string()) {
+      self.addrs.remove(&old);
+    }
+    if let Some(old_addr) = self.addrs.insert(name.to_string(), addr) && old_addr != addr {
+      self.labels.remove(&old_addr);
+    }
+  }
+
+  pub fn add_source(&mut self, addr: u16, line: usize, text: &str) {
+    self.source.insert(addr, (line, text.to_string()));
+  }
+
+  pub fn label_at(&self, addr: u16) -> Option<&str> {
+    self.labels.get(&addr).map(|s| s.as_str())
+  }
+
+  pub fn source_at(&self, addr: u16) -> Option<&(usize, String)> {
+    self.source.get(&addr)
+  }
+
+  pub fn lookup(&self, name: &str) -> Option<u16> {
+    self.addrs.get(name).copied()
+  }
+
+  /// Adds every entry of `other`, replacing labels and lines at the same address.
+  pub fn merge(&mut self, other: SymbolTable) {
+    for (addr, name) in other.labels {
+      self.add_label(addr, &name);
+    }
+    self.source.extend(other.source);
+  }
+
+  pub fn parse(text: &str) -> Result<Self, String> {
+    let mut table = SymbolTable::default();
+    for (i, line) in text.lines().enumerate() {
+      let line = line.trim();
+      if line.is_empty() || line.starts_with(';') {
+        continue;
+      }
+      let (kind, rest) = next_word(line);
+      let (addr, rest) = next_word(rest);
+      let addr = u16::from_str_radix(addr, 16).map_err(|_| format!("line {}: invalid address", i + 1))?;
+      match kind {
+        "sym" => {
+          let (name, _) = next_word(rest);
+          if name.is_empty() {
+            return Err(format!("line {}: missing symbol name", i + 1));
+          }
+          table.add_label(addr, name);
+        }
+        "src" => {
+          let (line_no, text) = next_word(rest);
+          let line_no = line_no.parse::<usize>().map_err(|_| format!("line {}: invalid source line number", i + 1))?;
+          table.add_source(addr, line_no, text);
+        }
+        _ => return Err(format!("line {}: unknown entry '{}'", i + 1, kind)),
+      }
+    }
+    Ok(table)
+  }
+
+  pub fn load(file: &str) -> Result<Self, String> {
+    let text = std::fs::read_to_string(file).map_err(|e| e.to_string())?;
+    SymbolTable::parse(&text)
+  }
+
+  pub fn serialize(&self) -> String {
+    let mut out = String::new();
+    for (addr, name) in self.labels.iter() {
+      out += &format!("sym {:04x} {}\n", addr, name);
+    }
+    for (addr, (line, text)) in self.source.iter() {
+      out += &format!("src {:04x} {} {}\n", addr, line, text);
+    }
+    out
+  }
+}
+
+/// The first whitespace-separated word of `text` and what follows it, trimmed.
+fn next_word(text: &str) -> (&str, &str) {
+  let text = text.trim_start();
+  let end = text.find(char::is_whitespace).unwrap_or(text.len());
+  (&text[..end], text[end..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_and_serializes() {
+    let table = SymbolTable::parse("; comment\nsym 0040   start\n\nsrc  0040 3 ld  r0, [r1]\nsym 0044\tloop\n").unwrap();
+    assert_eq!(table.lookup("start"), Some(0x40));
+    assert_eq!(table.label_at(0x44), Some("loop"));
+    assert_eq!(table.source_at(0x40), Some(&(3, String::from("ld  r0, [r1]"))));
+
+    let text = table.serialize();
+    assert_eq!(text, "sym 0040 start\nsym 0044 loop\nsrc 0040 3 ld  r0, [r1]\n");
+    assert_eq!(SymbolTable::parse(&text).unwrap().serialize(), text);
+
+    assert!(SymbolTable::parse("sym zz start").is_err());
+    assert!(SymbolTable::parse("sym 0040").is_err());
+    assert!(SymbolTable::parse("src 0040 x mov").is_err());
+  }
+
+  #[test]
+  fn replacing_a_label_forgets_the_old_one() {
+    let mut table = SymbolTable::default();
+    table.add_label(0x40, "old");
+    table.add_label(0x40, "new");
+    assert_eq!(table.lookup("old"), None);
+    assert_eq!(table.lookup("new"), Some(0x40));
+
+    let mut other = SymbolTable::default();
+    other.add_label(0x50, "new");
+    table.merge(other);
+    assert_eq!(table.label_at(0x40), None);
+    assert_eq!(table.lookup("new"), Some(0x50));
+    assert_eq!(table.len(), 1);
+  }
+}
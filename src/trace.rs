@@ -0,0 +1,254 @@
+use meivm2::opcode::Opcode;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+const MAGIC: &[u8; 4] = b"WVtr";
+const VERSION: u8 = 1;
+/// Register words live below this address; everything above is ordinary memory.
+const REGISTER_END: u16 = 0x40;
+
+#[derive(Debug)]
+pub enum TraceError {
+  Io(std::io::Error),
+  BadMagic([u8; 4]),
+  UnsupportedVersion(u8),
+  /// The file ends in the middle of the record starting at byte `offset`.
+  Truncated { offset: usize },
+}
+
+impl fmt::Display for TraceError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use TraceError::*;
+    match self {
+      Io(err) => write!(f, "{}", err),
+      BadMagic(magic) => write!(f, "Invalid trace magic number {:02x?}", magic),
+      UnsupportedVersion(version) => write!(f, "Unsupported trace version {}", version),
+      Truncated { offset } => write!(f, "Trace record at offset 0x{:x} is truncated", offset),
+    }
+  }
+}
+
+impl std::error::Error for TraceError {}
+
+impl From<std::io::Error> for TraceError {
+  fn from(err: std::io::Error) -> Self {
+    TraceError::Io(err)
+  }
+}
+
+/// One executed instruction.
+///
+/// On disk (big-endian): user u64, tick u64, pc u16, word u16, register count u8
+/// followed by (index u8, value u16) pairs, then write count u16 followed by
+/// (addr u16, value u16) pairs. The file starts with `WVtr` and a version byte.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TraceRecord {
+  pub user: u64,
+  pub tick: u64,
+  pub pc: u16,
+  pub word: u16,
+  /// Register words (`0x00..0x40`) the instruction changed, with their new values.
+  pub registers: Vec<(u8, u16)>,
+  /// Other memory words the instruction changed, with their new values.
+  pub writes: Vec<(u16, u16)>,
+}
+
+impl TraceRecord {
+  /// `changes` holds the address, old and new value of every word the tick changed.
+  pub fn new(user: u64, tick: u64, pc: u16, word: u16, changes: &[(u16, u16, u16)]) -> Self {
+    let registers = changes.iter()
+      .filter(|&&(addr, _, _)| addr < REGISTER_END)
+      .map(|&(addr, _, new)| (addr as u8, new))
+      .collect();
+    let writes = changes.iter()
+      .filter(|&&(addr, _, _)| addr >= REGISTER_END)
+      .map(|&(addr, _, new)| (addr, new))
+      .collect();
+    TraceRecord { user, tick, pc, word, registers, writes }
+  }
+
+  pub fn opcode(&self) -> Opcode {
+    Opcode::parse(self.word)
+  }
+
+  pub fn encode(&self, out: &mut Vec<u8>) {
+    out.extend_from_slice(&self.user.to_be_bytes());
+    out.extend_from_slice(&self.tick.to_be_bytes());
+    out.extend_from_slice(&self.pc.to_be_bytes());
+    out.extend_from_slice(&self.word.to_be_bytes());
+    out.push(self.registers.len() as u8);
+    for &(reg, value) in self.registers.iter() {
+      out.push(reg);
+      out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.extend_from_slice(&(self.writes.len() as u16).to_be_bytes());
+    for &(addr, value) in self.writes.iter() {
+      out.extend_from_slice(&addr.to_be_bytes());
+      out.extend_from_slice(&value.to_be_bytes());
+    }
+  }
+}
+
+impl fmt::Display for TraceRecord {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "U{} #{} {:04x}: {:04x} {}", self.user, self.tick, self.pc, self.word, self.opcode())?;
+    for &(reg, value) in self.registers.iter().filter(|&&(reg, _)| reg != 0x3c) {
+      write!(f, " {}={:04x}", component_name(reg), value)?;
+    }
+    for &(addr, value) in self.writes.iter() {
+      write!(f, " [{:04x}]={:04x}", addr, value)?;
+    }
+    Ok(())
+  }
+}
+
+pub fn register_name(reg: u8) -> String {
+  match reg {
+    0..=7 => format!("c{}", reg),
+    8..=14 => format!("r{}", reg - 8),
+    _ => String::from("ri"),
+  }
+}
+
+/// Name of one word of the register file, as in `r2.y`.
+pub fn component_name(word: u8) -> String {
+  format!("{}.{}", register_name(word / 4), ["x", "y", "z", "w"][word as usize % 4])
+}
+
+/// Streams records to a trace file as the sim thread executes them.
+pub struct TraceWriter {
+  out: BufWriter<File>,
+  pub path: String,
+  pub records: u64,
+  buffer: Vec<u8>,
+}
+
+impl TraceWriter {
+  pub fn create(path: &str) -> Result<Self, TraceError> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])?;
+    Ok(TraceWriter { out, path: path.to_string(), records: 0, buffer: Vec::new() })
+  }
+
+  pub fn write(&mut self, record: &TraceRecord) -> Result<(), TraceError> {
+    self.buffer.clear();
+    record.encode(&mut self.buffer);
+    self.out.write_all(&self.buffer)?;
+    self.records += 1;
+    Ok(())
+  }
+
+  /// Flushes the file and returns the number of records written.
+  pub fn finish(mut self) -> Result<u64, TraceError> {
+    self.out.flush()?;
+    Ok(self.records)
+  }
+}
+
+impl fmt::Debug for TraceWriter {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "TraceWriter({}, {} records)", self.path, self.records)
+  }
+}
+
+pub fn parse_trace(buffer: &[u8]) -> Result<Vec<TraceRecord>, TraceError> {
+  if buffer.len() < 5 {
+    return Err(TraceError::Truncated { offset: 0 });
+  }
+  let magic: [u8; 4] = buffer[0..4].try_into().unwrap();
+  if &magic != MAGIC {
+    return Err(TraceError::BadMagic(magic));
+  }
+  if buffer[4] != VERSION {
+    return Err(TraceError::UnsupportedVersion(buffer[4]));
+  }
+
+  let mut records = Vec::new();
+  let mut pos = 5;
+  while pos < buffer.len() {
+    let start = pos;
+    let mut take = |n: usize| -> Result<&[u8], TraceError> {
+      let bytes = buffer.get(pos..pos + n).ok_or(TraceError::Truncated { offset: start })?;
+      pos += n;
+      Ok(bytes)
+    };
+    let user = u64::from_be_bytes(take(8)?.try_into().unwrap());
+    let tick = u64::from_be_bytes(take(8)?.try_into().unwrap());
+    let pc = u16::from_be_bytes(take(2)?.try_into().unwrap());
+    let word = u16::from_be_bytes(take(2)?.try_into().unwrap());
+    let count = take(1)?[0] as usize;
+    let mut registers = Vec::with_capacity(count);
+    for _ in 0..count {
+      let bytes = take(3)?;
+      registers.push((bytes[0], u16::from_be_bytes([bytes[1], bytes[2]])));
+    }
+    let count = u16::from_be_bytes(take(2)?.try_into().unwrap()) as usize;
+    let mut writes = Vec::with_capacity(count);
+    for _ in 0..count {
+      let bytes = take(4)?;
+      writes.push((u16::from_be_bytes([bytes[0], bytes[1]]), u16::from_be_bytes([bytes[2], bytes[3]])));
+    }
+    records.push(TraceRecord { user, tick, pc, word, registers, writes });
+  }
+  Ok(records)
+}
+
+pub fn load_trace(path: &str) -> Result<Vec<TraceRecord>, TraceError> {
+  parse_trace(&std::fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn splits_and_encodes_changes() {
+    let record = TraceRecord::new(3, 1, 0x40, 0x1234, &[(0x3c, 0x40, 0x41), (0x20, 0, 7), (0x1000, 1, 2)]);
+    assert_eq!(record.registers, vec![(0x3c, 0x41), (0x20, 7)]);
+    assert_eq!(record.writes, vec![(0x1000, 2)]);
+
+    let mut buffer = Vec::new();
+    record.encode(&mut buffer);
+    assert_eq!(buffer.len(), 8 + 8 + 2 + 2 + 1 + 2 * 3 + 2 + 4);
+    assert_eq!(&buffer[..8], &3u64.to_be_bytes());
+    assert_eq!(&buffer[16..20], &[0x00, 0x40, 0x12, 0x34]);
+    assert_eq!(&buffer[buffer.len() - 6..], &[0x00, 0x01, 0x10, 0x00, 0x00, 0x02]);
+  }
+
+  fn image(records: &[TraceRecord]) -> Vec<u8> {
+    let mut buffer = MAGIC.to_vec();
+    buffer.push(VERSION);
+    for record in records {
+      record.encode(&mut buffer);
+    }
+    buffer
+  }
+
+  #[test]
+  fn records_round_trip() {
+    let records = vec![
+      TraceRecord::new(0, 1, 0x40, 0x1234, &[(0x3c, 0x40, 0x41), (0x20, 0, 7), (0x1000, 1, 2)]),
+      TraceRecord::new(3, 2, 0x41, 0x0000, &[(0x3c, 0x41, 0x42)]),
+    ];
+    assert_eq!(records[0].registers, vec![(0x3c, 0x41), (0x20, 7)]);
+    assert_eq!(records[0].writes, vec![(0x1000, 2)]);
+    assert_eq!(parse_trace(&image(&records)).unwrap(), records);
+    assert_eq!(parse_trace(&image(&[])).unwrap(), vec![]);
+  }
+
+  #[test]
+  fn rejects_bad_files() {
+    assert!(matches!(parse_trace(b"WVt"), Err(TraceError::Truncated { offset: 0 })));
+    assert!(matches!(parse_trace(b"MWvm\x01"), Err(TraceError::BadMagic(_))));
+    assert!(matches!(parse_trace(b"WVtr\x09"), Err(TraceError::UnsupportedVersion(9))));
+
+    let record = TraceRecord::new(0, 1, 0x40, 0x1234, &[(0x3c, 0x40, 0x41), (0x1000, 1, 2)]);
+    let full = image(&[record.clone(), record]);
+    let second = (full.len() - 5) / 2 + 5;
+    for len in second + 1..full.len() {
+      assert!(matches!(parse_trace(&full[..len]), Err(TraceError::Truncated { offset }) if offset == second));
+    }
+  }
+}
@@ -0,0 +1,215 @@
+use std::path::Path;
+
+use crate::trace::{component_name, load_trace, TraceRecord};
+
+/// How far past the split to look for the two runs reaching the same PC again.
+const REJOIN_WINDOW: usize = 256;
+
+/// How the two runs differ at the first divergence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DivergenceKind {
+  /// A tick was recorded in the first trace only.
+  OnlyInA,
+  /// A tick was recorded in the second trace only.
+  OnlyInB,
+  /// Both traces recorded the tick, with different PC, registers or writes.
+  Differs,
+}
+
+/// The first tick, lined up by `(tick, user)`, at which the runs do not match.
+/// `a` and `b` are the positions reached in each trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+  pub a: usize,
+  pub b: usize,
+  pub kind: DivergenceKind,
+  pub reason: String,
+}
+
+/// Where the PC sequences of the two runs part, and where they meet again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlSplit {
+  /// Positions in each trace of the first records with different PCs.
+  pub a: usize,
+  pub b: usize,
+  /// Offsets past the split at which both runs reach the same PC.
+  pub rejoin: Option<(usize, usize)>,
+}
+
+fn describe_registers(registers: &[(u8, u16)]) -> String {
+  registers.iter()
+    .map(|&(reg, value)| format!("{}={:04x}", component_name(reg), value))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+fn describe_writes(writes: &[(u16, u16)]) -> String {
+  writes.iter()
+    .map(|&(addr, value)| format!("[{:04x}]={:04x}", addr, value))
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+/// Merges both traces on `(tick, user)` and returns the first tick recorded
+/// on one side only, or recorded on both with a different PC, instruction,
+/// changed registers or memory writes.
+pub fn first_divergence(a: &[TraceRecord], b: &[TraceRecord]) -> Option<Divergence> {
+  let (mut i, mut j) = (0, 0);
+  loop {
+    let key = |record: &TraceRecord| (record.tick, record.user);
+    let (kind, reason) = match (a.get(i), b.get(j)) {
+      (None, None) => return None,
+      (Some(x), y) if y.is_none_or(|y| key(x) < key(y)) => {
+        (DivergenceKind::OnlyInA, format!("tick {} (U{}) is only in the first trace", x.tick, x.user))
+      }
+      (x, Some(y)) if x.is_none_or(|x| key(y) < key(x)) => {
+        (DivergenceKind::OnlyInB, format!("tick {} (U{}) is only in the second trace", y.tick, y.user))
+      }
+      (Some(x), Some(y)) => {
+        let reason = if x.pc != y.pc {
+          format!("pc {:04x} vs {:04x}", x.pc, y.pc)
+        } else if x.word != y.word {
+          format!("instruction {:04x} vs {:04x}", x.word, y.word)
+        } else if x.registers != y.registers {
+          format!("registers {} vs {}", describe_registers(&x.registers), describe_registers(&y.registers))
+        } else if x.writes != y.writes {
+          format!("memory writes {} vs {}", describe_writes(&x.writes), describe_writes(&y.writes))
+        } else {
+          i += 1;
+          j += 1;
+          continue;
+        };
+        (DivergenceKind::Differs, reason)
+      }
+      _ => unreachable!(),
+    };
+    return Some(Divergence { a: i, b: j, kind, reason });
+  }
+}
+
+/// Finds the first records from `start_a` and `start_b` on where the two runs
+/// execute different PCs, then the nearest pair of later records where they
+/// agree again.
+pub fn control_split(a: &[TraceRecord], b: &[TraceRecord], start_a: usize, start_b: usize) -> Option<ControlSplit> {
+  let offset = (0..a.len().saturating_sub(start_a).min(b.len().saturating_sub(start_b)))
+    .find(|&k| a[start_a + k].pc != b[start_b + k].pc)?;
+  let (split_a, split_b) = (start_a + offset, start_b + offset);
+  let mut rejoin: Option<(usize, usize)> = None;
+  for i in 0..REJOIN_WINDOW.min(a.len() - split_a) {
+    for j in 0..REJOIN_WINDOW.min(b.len() - split_b) {
+      if rejoin.is_some_and(|(ri, rj)| ri + rj <= i + j) {
+        break;
+      }
+      if a[split_a + i].pc == b[split_b + j].pc {
+        rejoin = Some((i, j));
+      }
+    }
+  }
+  Some(ControlSplit { a: split_a, b: split_b, rejoin })
+}
+
+/// Loads two traces and prints where they diverge. Returns the process exit
+/// code: 0 if the runs match, 2 if they differ, 1 on error.
+pub fn run(a_path: &Path, b_path: &Path, context: usize) -> i32 {
+  let load = |path: &Path| load_trace(path.to_str().unwrap()).map_err(|err| {
+    eprintln!("Failed to load {}: {}", path.display(), err);
+  });
+  let (Ok(a), Ok(b)) = (load(a_path), load(b_path)) else {
+    return 1;
+  };
+
+  println!("{}: {} records, {}: {} records", a_path.display(), a.len(), b_path.display(), b.len());
+  let Some(div) = first_divergence(&a, &b) else {
+    println!("Traces are identical");
+    return 0;
+  };
+
+  let tick = a.get(div.a).or(b.get(div.b)).map(|r| r.tick).unwrap_or_default();
+  println!("First difference at record {} of a, {} of b (tick {}): {}", div.a, div.b, tick, div.reason);
+  for record in a[div.a.saturating_sub(context)..div.a].iter() {
+    println!("    {}", record);
+  }
+  for record in a.iter().skip(div.a).take(context + 1) {
+    println!("  a {}", record);
+  }
+  for record in b.iter().skip(div.b).take(context + 1) {
+    println!("  b {}", record);
+  }
+
+  match control_split(&a, &b, div.a, div.b) {
+    Some(split) => {
+      let from = split.a.checked_sub(1).map(|i| format!("{:04x}", a[i].pc)).unwrap_or(String::from("start"));
+      println!(
+        "Control flow splits at record {} of a, {} of b after {}: a goes to {:04x}, b goes to {:04x}",
+        split.a, split.b, from, a[split.a].pc, b[split.b].pc,
+      );
+      match split.rejoin {
+        Some((i, j)) => println!(
+          "Runs meet again at {:04x} after {} instructions in a and {} in b",
+          a[split.a + i].pc, i, j,
+        ),
+        None => println!("Runs do not meet again within {} instructions", REJOIN_WINDOW),
+      }
+    }
+    None => println!("Control flow is the same in both runs"),
+  }
+  2
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn run_of(pcs: &[u16]) -> Vec<TraceRecord> {
+    pcs.iter().enumerate()
+      .map(|(i, &pc)| TraceRecord::new(0, i as u64 + 1, pc, 0, &[(0x3c, pc, pc + 1)]))
+      .collect()
+  }
+
+  #[test]
+  fn finds_first_divergence() {
+    let a = run_of(&[0x40, 0x41, 0x42]);
+    assert_eq!(first_divergence(&a, &a), None);
+
+    let mut b = a.clone();
+    b[1].registers.push((0x20, 5));
+    let div = first_divergence(&a, &b).unwrap();
+    assert_eq!((div.a, div.b, div.kind), (1, 1, DivergenceKind::Differs));
+    assert!(div.reason.starts_with("registers") && div.reason.contains("r0.x=0005"));
+
+    let mut b = a.clone();
+    b[2].writes.push((0x1000, 1));
+    assert!(first_divergence(&a, &b).unwrap().reason.contains("[1000]=0001"));
+
+    let div = first_divergence(&a, &a[..2]).unwrap();
+    assert_eq!((div.a, div.b, div.kind), (2, 2, DivergenceKind::OnlyInA));
+    assert!(div.reason.contains("tick 3 (U0) is only in the first"));
+  }
+
+  #[test]
+  fn lines_runs_up_by_tick() {
+    // The second run misses tick 2 but agrees on every other tick.
+    let a = run_of(&[0x40, 0x41, 0x42, 0x43]);
+    let b = vec![a[0].clone(), a[2].clone(), a[3].clone()];
+    let div = first_divergence(&a, &b).unwrap();
+    assert_eq!((div.a, div.b, div.kind), (1, 1, DivergenceKind::OnlyInA));
+    assert_eq!(first_divergence(&b, &a).unwrap().kind, DivergenceKind::OnlyInB);
+
+    // Once the extra record is skipped the runs match again.
+    assert_eq!(first_divergence(&a[2..], &b[1..]), None);
+  }
+
+  #[test]
+  fn finds_control_split_and_rejoin() {
+    let a = run_of(&[0x40, 0x41, 0x50, 0x51, 0x60]);
+    let b = run_of(&[0x40, 0x41, 0x55, 0x56, 0x57, 0x60]);
+    let split = control_split(&a, &b, 0, 0).unwrap();
+    assert_eq!((split.a, split.b), (2, 2));
+    assert_eq!(split.rejoin, Some((2, 3)));
+    assert_eq!(control_split(&a, &b[1..], 1, 0).unwrap().a, 2);
+
+    let b = run_of(&[0x40, 0x41, 0x70]);
+    assert_eq!(control_split(&a, &b, 0, 0).unwrap().rejoin, None);
+    assert_eq!(control_split(&a, &a, 0, 0), None);
+  }
+}
@@ -52,6 +52,19 @@ pub fn render_hex(frame: &mut Frame, value: u16, x: u16, y: u16, color: Option<C
   render_string(frame, format!("{:04x}", value), x, y, 4, color);
 }
 
+pub fn parse_hex_arg(s: &str) -> Result<u16, String> {
+  let s = s.trim_start_matches("0x");
+  u16::from_str_radix(s, 16).map_err(|e| format!("Invalid hex value '{}': {}", s, e))
+}
+
+/// Parses `ADDR[:LEN]` in hex. The length defaults to a single vector (4 words).
+pub fn parse_range_arg(s: &str) -> Result<(u16, u16), String> {
+  match s.split_once(':') {
+    Some((addr, len)) => Ok((parse_hex_arg(addr)?, parse_hex_arg(len)?)),
+    None => Ok((parse_hex_arg(s)?, 4)),
+  }
+}
+
 pub fn rect_within(rect: Rect, parent: Rect) -> Rect {
   let x = rect.x + parent.x;
   let y = rect.y + parent.y;
@@ -83,6 +96,26 @@ pub fn attach_to(rect: Rect, parent: Rect, side: AttachSide) -> Rect {
   Rect::new(x, y, rect.width, rect.height)
 }
 
+/// Blue for cold through red for hot, `heat` in 0..=1.
+pub fn heat_color(heat: f32) -> Color {
+  let heat = heat.clamp(0.0, 1.0);
+  let r = (64.0 + heat * 191.0) as u8;
+  let g = (64.0 + (1.0 - (heat * 2.0 - 1.0).abs()) * 128.0) as u8;
+  let b = (64.0 + (1.0 - heat) * 191.0) as u8;
+  Color::Rgb(r, g, b)
+}
+
+/// Darkens an RGB color toward black, `amount` in 0..=1. Other colors are left alone.
+pub fn fade_color(color: Color, amount: f32) -> Color {
+  match color {
+    Color::Rgb(r, g, b) => {
+      let keep = 1.0 - amount.clamp(0.0, 1.0);
+      Color::Rgb((r as f32 * keep) as u8, (g as f32 * keep) as u8, (b as f32 * keep) as u8)
+    }
+    color => color,
+  }
+}
+
 pub fn color_from_value(value: u16) -> Color {
   if value == 0 { return Color::Rgb(64, 64, 64); }
 
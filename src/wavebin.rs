@@ -1,59 +1,509 @@
-use core::panic;
-use std::{io::Read, vec};
+use meivm2::MEM_SHARED_SIZE_U;
+use std::fmt;
+use std::path::Path;
+
+use crate::assembler::{assemble_with_symbols, AsmError};
+use crate::symbols::SymbolTable;
+use std::{io::{Read, Write}, vec};
+
+#[derive(Debug)]
+pub enum WaveBinError {
+  Io(std::io::Error),
+  /// The file ends before the header (or section table) is complete.
+  TruncatedHeader { len: usize, needed: usize },
+  BadMagic([u8; 4]),
+  UnsupportedVersion(u8),
+  /// A section's byte range `start..end` is inverted or runs past the end of the file.
+  BadOffsets { start: usize, end: usize, file_len: usize },
+  /// A section starting at byte `offset` has an odd number of bytes.
+  OddLength { offset: usize, len: usize },
+  /// A section does not fit in VM memory when placed at `addr`.
+  OversizeImage { addr: u16, words: usize },
+  ChecksumMismatch { section: usize, offset: usize, expected: u32, actual: u32 },
+  /// The v2 section table can only hold 255 entries.
+  TooManySections(usize),
+  Asm(AsmError),
+}
+
+impl fmt::Display for WaveBinError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use WaveBinError::*;
+    match self {
+      Io(err) => write!(f, "{}", err),
+      TruncatedHeader { len, needed } => write!(f, "Truncated header: file is {} bytes, header needs {}", len, needed),
+      BadMagic(magic) => write!(f, "Invalid magic number {:02x?} at offset 0x0", magic),
+      UnsupportedVersion(version) => write!(f, "Unsupported version {} at offset 0x4", version),
+      BadOffsets { start, end, file_len } => write!(f, "Bad section offsets 0x{:x}..0x{:x} (file is 0x{:x} bytes)", start, end, file_len),
+      OddLength { offset, len } => write!(f, "Section at offset 0x{:x} has odd length {}", offset, len),
+      OversizeImage { addr, words } => write!(f, "Section of {} words at {:04x} does not fit in memory", words, addr),
+      ChecksumMismatch { section, offset, expected, actual } => write!(f, "Section {} at offset 0x{:x} checksum mismatch (expected {:08x}, got {:08x})", section, offset, expected, actual),
+      TooManySections(count) => write!(f, "Too many sections: {} (max 255)", count),
+      Asm(err) => write!(f, "{}", err),
+    }
+  }
+}
+
+impl std::error::Error for WaveBinError {}
+
+impl From<std::io::Error> for WaveBinError {
+  fn from(err: std::io::Error) -> Self {
+    WaveBinError::Io(err)
+  }
+}
+
+/// A block of words placed at `addr` when the image is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+  pub addr: u16,
+  pub flags: u16,
+  pub data: Vec<u16>,
+}
+
+impl Section {
+  pub const CODE: u16 = 1 << 0;
+  pub const DATA: u16 = 1 << 1;
+  pub const CONST_STORE: u16 = 1 << 2;
+  pub const MODULE_CONFIG: u16 = 1 << 3;
+
+  pub fn kind(&self) -> &'static str {
+    if self.flags & Section::CODE != 0 {
+      "code"
+    } else if self.flags & Section::CONST_STORE != 0 {
+      "const"
+    } else if self.flags & Section::MODULE_CONFIG != 0 {
+      "module"
+    } else {
+      "data"
+    }
+  }
+}
 
 pub struct WaveVMBin {
-  pub mem: Vec<u16>,
-  pub code: Vec<u16>,
+  pub version: u8,
+  pub sections: Vec<Section>,
+  pub symbols: SymbolTable,
+}
+
+impl WaveVMBin {
+  /// Builds a v2 image from `memory[start..start + len]`, split at the
+  /// register, code and module bus boundaries so each section is flagged
+  /// for what it holds.
+  pub fn from_memory(memory: &[u16], start: usize, len: usize) -> Self {
+    let end = (start + len).min(memory.len());
+    let mut sections = Vec::new();
+    let mut addr = start;
+    while addr < end {
+      let (region_end, flags) = match addr {
+        0x00..0x40 => (0x40, Section::DATA),
+        0x40..0x100 => (0x100, Section::CODE),
+        0x100..0x300 => (0x300, Section::DATA),
+        0x300..0x400 => (0x400, Section::MODULE_CONFIG),
+        _ => (end, Section::DATA),
+      };
+      let section_end = region_end.min(end);
+      sections.push(Section {
+        addr: addr as u16,
+        flags,
+        data: memory[addr..section_end].to_vec(),
+      });
+      addr = section_end;
+    }
+    WaveVMBin { version: 2, sections, symbols: SymbolTable::default() }
+  }
+
+  pub fn word_count(&self) -> usize {
+    self.sections.iter().map(|s| s.data.len()).sum()
+  }
+}
+
+/// Size of the v2 header: magic, version, section count and two reserved bytes.
+const V2_HEADER_SIZE: usize = 8;
+/// Size of a v2 section table entry: addr, len, flags (u16) and offset, crc (u32).
+const V2_ENTRY_SIZE: usize = 14;
+
+/// CRC-32 (IEEE 802.3, reflected) over the section bytes as stored in the file.
+pub fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = 0xffff_ffffu32;
+  for &b in bytes {
+    crc ^= b as u32;
+    for _ in 0..8 {
+      let mask = (crc & 1).wrapping_neg();
+      crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+    }
+  }
+  !crc
 }
 
-pub fn load_wavevm_bin(file: &str) -> Result<WaveVMBin, std::io::Error> {
+pub fn load_wavevm_bin(file: &str) -> Result<WaveVMBin, WaveBinError> {
   let mut file = std::fs::File::open(file)?;
   let mut buffer = Vec::new();
   file.read_to_end(&mut buffer)?;
+  parse_wavevm_bin(&buffer)
+}
+
+/// Loads a `.wasm` source file through the assembler, or anything else as a binary image.
+/// Symbols from a `<file>.sym` sidecar are added when one exists.
+pub fn load_program(file: &str) -> Result<WaveVMBin, WaveBinError> {
+  let mut bin = if Path::new(file).extension().is_some_and(|ext| ext == "wasm") {
+    let source = std::fs::read_to_string(file)?;
+    let (sections, symbols) = assemble_with_symbols(&source).map_err(WaveBinError::Asm)?;
+    WaveVMBin { version: 2, sections, symbols }
+  } else {
+    load_wavevm_bin(file)?
+  };
+
+  let sym_path = format!("{}.sym", file);
+  if Path::new(&sym_path).exists() {
+    match SymbolTable::load(&sym_path) {
+      Ok(symbols) => bin.symbols.merge(symbols),
+      Err(err) => warn!("Ignoring symbol file {}: {}", sym_path, err),
+    }
+  }
+  Ok(bin)
+}
+
+/// Returns `buffer[start..end]` as big-endian words after checking the range
+/// lies within the file, has an even length and fits in memory at `addr`.
+fn read_words(buffer: &[u8], start: usize, end: usize, addr: u16) -> Result<Vec<u16>, WaveBinError> {
+  if start > end || end > buffer.len() {
+    return Err(WaveBinError::BadOffsets { start, end, file_len: buffer.len() });
+  }
+  let len = end - start;
+  if len & 1 != 0 {
+    return Err(WaveBinError::OddLength { offset: start, len });
+  }
+  let words = len / 2;
+  if addr as usize + words > MEM_SHARED_SIZE_U {
+    return Err(WaveBinError::OversizeImage { addr, words });
+  }
+  Ok(buffer[start..end].chunks_exact(2)
+    .map(|w| u16::from_be_bytes([w[0], w[1]]))
+    .collect())
+}
+
+pub fn parse_wavevm_bin(buffer: &[u8]) -> Result<WaveVMBin, WaveBinError> {
+  if buffer.len() < 5 {
+    return Err(WaveBinError::TruncatedHeader { len: buffer.len(), needed: 5 });
+  }
 
   // Check for magic numbers
   if &buffer[0..4] != b"MWvm" {
-    return Err(std::io::Error::new(
-      std::io::ErrorKind::InvalidData,
-      "Invalid magic number",
-    ));
+    return Err(WaveBinError::BadMagic([buffer[0], buffer[1], buffer[2], buffer[3]]));
   }
 
-  let version = buffer[4] as usize;
+  let version = buffer[4];
 
   match version {
     1 => {
+      if buffer.len() < 7 {
+        return Err(WaveBinError::TruncatedHeader { len: buffer.len(), needed: 7 });
+      }
       let mem_start = buffer[5] as usize;
       let code_start = buffer[6] as usize;
 
-      let mem_size = code_start - mem_start;
-      let code_size = buffer.len() - code_start;
-
-      let mut mem = vec![0; mem_size];
-      let mut code = vec![0; code_size];
+      let mem = read_words(buffer, mem_start, code_start, 0)?;
+      let code = read_words(buffer, code_start, buffer.len(), 0x40)?;
 
-      if mem_size % 2 != 0 {
-        panic!("Memory size is not even. {}, {}, {}, {}", mem_start, mem_size, code_start, code_size);
+      Ok(WaveVMBin {
+        version: 1,
+        sections: vec![
+          Section { addr: 0, flags: Section::DATA, data: mem },
+          Section { addr: 0x40, flags: Section::CODE, data: code },
+        ],
+        symbols: SymbolTable::default(),
+      })
+    }
+    2 => {
+      if buffer.len() < V2_HEADER_SIZE {
+        return Err(WaveBinError::TruncatedHeader { len: buffer.len(), needed: V2_HEADER_SIZE });
       }
-      for i in (0..mem_size).step_by(2) {
-        mem[i / 2] = u16::from_be_bytes([buffer[mem_start + i], buffer[mem_start + i + 1]]);
+      let count = buffer[5] as usize;
+      let table_end = V2_HEADER_SIZE + count * V2_ENTRY_SIZE;
+      if buffer.len() < table_end {
+        return Err(WaveBinError::TruncatedHeader { len: buffer.len(), needed: table_end });
       }
 
-      if code_size % 2 != 0 {
-        panic!("Code size is not even. {}, {}, {}, {}", mem_start, mem_size, code_start, code_size);
-      }
-      for i in (0..code_size).step_by(2) {
-        code[i / 2] = u16::from_be_bytes([buffer[code_start + i], buffer[code_start + i + 1]]);
+      let mut sections = Vec::with_capacity(count);
+      for n in 0..count {
+        let entry = &buffer[V2_HEADER_SIZE + n * V2_ENTRY_SIZE..][..V2_ENTRY_SIZE];
+        let addr = u16::from_be_bytes([entry[0], entry[1]]);
+        let len = u16::from_be_bytes([entry[2], entry[3]]) as usize;
+        let flags = u16::from_be_bytes([entry[4], entry[5]]);
+        let offset = u32::from_be_bytes([entry[6], entry[7], entry[8], entry[9]]) as usize;
+        let crc = u32::from_be_bytes([entry[10], entry[11], entry[12], entry[13]]);
+
+        let data = read_words(buffer, offset, offset + len * 2, addr)?;
+        let actual = crc32(&buffer[offset..offset + len * 2]);
+        if actual != crc {
+          return Err(WaveBinError::ChecksumMismatch { section: n, offset, expected: crc, actual });
+        }
+        sections.push(Section { addr, flags, data });
       }
 
-      Ok(WaveVMBin { mem, code })
+      Ok(WaveVMBin { version: 2, sections, symbols: SymbolTable::default() })
     }
     _ => {
       error!("Unsupported version: {}", version);
-      Err(std::io::Error::new(
-        std::io::ErrorKind::InvalidData,
-        "Unsupported version",
-      ))
+      Err(WaveBinError::UnsupportedVersion(version))
+    }
+  }
+}
+
+/// Serializes `bin` in the v2 format, regardless of the version it was loaded from.
+pub fn serialize_wavevm_bin(bin: &WaveVMBin) -> Result<Vec<u8>, WaveBinError> {
+  let count = bin.sections.len();
+  if count > u8::MAX as usize {
+    return Err(WaveBinError::TooManySections(count));
+  }
+
+  let mut buffer = b"MWvm".to_vec();
+  buffer.extend_from_slice(&[2, count as u8, 0, 0]);
+
+  let mut offset = V2_HEADER_SIZE + count * V2_ENTRY_SIZE;
+  let mut body = Vec::new();
+  for section in bin.sections.iter() {
+    if section.addr as usize + section.data.len() > MEM_SHARED_SIZE_U {
+      return Err(WaveBinError::OversizeImage { addr: section.addr, words: section.data.len() });
+    }
+    let bytes = section.data.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<u8>>();
+    buffer.extend_from_slice(&section.addr.to_be_bytes());
+    buffer.extend_from_slice(&(section.data.len() as u16).to_be_bytes());
+    buffer.extend_from_slice(&section.flags.to_be_bytes());
+    buffer.extend_from_slice(&(offset as u32).to_be_bytes());
+    buffer.extend_from_slice(&crc32(&bytes).to_be_bytes());
+    offset += bytes.len();
+    body.extend(bytes);
+  }
+  buffer.extend(body);
+  Ok(buffer)
+}
+
+pub fn write_wavevm_bin(file: &str, bin: &WaveVMBin) -> Result<(), WaveBinError> {
+  let buffer = serialize_wavevm_bin(bin)?;
+  let mut file = std::fs::File::create(file)?;
+  file.write_all(&buffer)?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn v1_image(mem: &[u16], code: &[u16]) -> Vec<u8> {
+    let mut buffer = b"MWvm".to_vec();
+    buffer.push(1);
+    buffer.push(7);
+    buffer.push((7 + mem.len() * 2) as u8);
+    for w in mem.iter().chain(code.iter()) {
+      buffer.extend_from_slice(&w.to_be_bytes());
+    }
+    buffer
+  }
+
+  fn v2_image(sections: &[(u16, u16, &[u16])]) -> Vec<u8> {
+    let mut buffer = b"MWvm".to_vec();
+    buffer.extend_from_slice(&[2, sections.len() as u8, 0, 0]);
+    let mut offset = V2_HEADER_SIZE + sections.len() * V2_ENTRY_SIZE;
+    let mut body = Vec::new();
+    for &(addr, flags, data) in sections {
+      let bytes = data.iter().flat_map(|w| w.to_be_bytes()).collect::<Vec<u8>>();
+      buffer.extend_from_slice(&addr.to_be_bytes());
+      buffer.extend_from_slice(&(data.len() as u16).to_be_bytes());
+      buffer.extend_from_slice(&flags.to_be_bytes());
+      buffer.extend_from_slice(&(offset as u32).to_be_bytes());
+      buffer.extend_from_slice(&crc32(&bytes).to_be_bytes());
+      offset += bytes.len();
+      body.extend(bytes);
+    }
+    buffer.extend(body);
+    buffer
+  }
+
+  /// xorshift64, so the fuzz cases are the same on every run.
+  struct Rng(u64);
+
+  impl Rng {
+    fn next(&mut self) -> u64 {
+      self.0 ^= self.0 << 13;
+      self.0 ^= self.0 >> 7;
+      self.0 ^= self.0 << 17;
+      self.0
+    }
+  }
+
+  #[test]
+  fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+  }
+
+  #[test]
+  fn parses_v1() {
+    let bin = parse_wavevm_bin(&v1_image(&[1, 2], &[0x1234, 0x5678, 0x9abc])).unwrap();
+    assert_eq!(bin.version, 1);
+    assert_eq!(bin.sections[0], Section { addr: 0, flags: Section::DATA, data: vec![1, 2] });
+    assert_eq!(bin.sections[1], Section { addr: 0x40, flags: Section::CODE, data: vec![0x1234, 0x5678, 0x9abc] });
+  }
+
+  #[test]
+  fn parses_v2() {
+    let image = v2_image(&[(0x40, Section::CODE, &[0xaaaa, 0xbbbb]), (0x1000, Section::DATA, &[7])]);
+    let bin = parse_wavevm_bin(&image).unwrap();
+    assert_eq!(bin.version, 2);
+    assert_eq!(bin.sections[0], Section { addr: 0x40, flags: Section::CODE, data: vec![0xaaaa, 0xbbbb] });
+    assert_eq!(bin.sections[1], Section { addr: 0x1000, flags: Section::DATA, data: vec![7] });
+  }
+
+  #[test]
+  fn rejects_truncated_header() {
+    for len in 0..5 {
+      assert!(matches!(parse_wavevm_bin(&b"MWvm\x01"[..len]), Err(WaveBinError::TruncatedHeader { .. })));
+    }
+    assert!(matches!(parse_wavevm_bin(b"MWvm\x01\x07"), Err(WaveBinError::TruncatedHeader { needed: 7, .. })));
+    assert!(matches!(parse_wavevm_bin(b"MWvm\x02\x03\x00\x00"), Err(WaveBinError::TruncatedHeader { .. })));
+  }
+
+  #[test]
+  fn rejects_bad_magic() {
+    assert!(matches!(parse_wavevm_bin(b"WVMm\x01\x07\x07"), Err(WaveBinError::BadMagic(m)) if &m == b"WVMm"));
+  }
+
+  #[test]
+  fn rejects_unsupported_version() {
+    assert!(matches!(parse_wavevm_bin(b"MWvm\x09\x07\x07"), Err(WaveBinError::UnsupportedVersion(9))));
+  }
+
+  #[test]
+  fn rejects_bad_offsets() {
+    // Code before memory used to underflow.
+    let mut image = v1_image(&[1, 2], &[3]);
+    image[5] = 9;
+    image[6] = 7;
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::BadOffsets { start: 9, end: 7, .. })));
+    // Code offset past the end of the file.
+    image[5] = 7;
+    image[6] = 0xff;
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::BadOffsets { end: 0xff, .. })));
+  }
+
+  #[test]
+  fn rejects_odd_length() {
+    let mut image = v1_image(&[1], &[2]);
+    image.push(0);
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::OddLength { offset: 9, len: 3 })));
+    image[6] = 8;
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::OddLength { offset: 7, len: 1 })));
+  }
+
+  #[test]
+  fn rejects_oversize_image() {
+    let image = v2_image(&[((MEM_SHARED_SIZE_U - 1) as u16, Section::DATA, &[1, 2])]);
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::OversizeImage { words: 2, .. })));
+  }
+
+  #[test]
+  fn rejects_checksum_mismatch() {
+    let mut image = v2_image(&[(0x40, Section::CODE, &[0xaaaa, 0xbbbb])]);
+    let last = image.len() - 1;
+    image[last] ^= 1;
+    assert!(matches!(parse_wavevm_bin(&image), Err(WaveBinError::ChecksumMismatch { section: 0, offset: 22, .. })));
+  }
+
+  #[test]
+  fn every_truncation_is_handled() {
+    // v1 has no length field, so cutting whole code words still parses.
+    let v1 = v1_image(&[1, 2, 3, 4], &[5, 6, 7]);
+    for len in 0..v1.len() {
+      let _ = parse_wavevm_bin(&v1[..len]);
+    }
+    let v2 = v2_image(&[(0x40, Section::CODE, &[1, 2, 3]), (0x380, Section::MODULE_CONFIG, &[4, 5])]);
+    assert!(parse_wavevm_bin(&v2).is_ok());
+    for len in 0..v2.len() {
+      assert!(parse_wavevm_bin(&v2[..len]).is_err());
+    }
+  }
+
+  #[test]
+  fn v1_header_offsets_never_panic() {
+    let mut image = v1_image(&[1, 2, 3], &[4, 5, 6, 7]);
+    for mem_start in 0..=255u8 {
+      for code_start in 0..=255u8 {
+        image[5] = mem_start;
+        image[6] = code_start;
+        let _ = parse_wavevm_bin(&image);
+      }
+    }
+  }
+
+  #[test]
+  fn serializes_same_bytes_as_reference() {
+    let image = v2_image(&[(0x40, Section::CODE, &[0xaaaa, 0xbbbb]), (0x1000, Section::DATA, &[7])]);
+    let bin = parse_wavevm_bin(&image).unwrap();
+    assert_eq!(serialize_wavevm_bin(&bin).unwrap(), image);
+  }
+
+  #[test]
+  fn load_save_load_round_trip() {
+    let v1 = parse_wavevm_bin(&v1_image(&[1, 2, 3], &[0x1234, 0x5678])).unwrap();
+    let mut memory = vec![0u16; MEM_SHARED_SIZE_U];
+    for section in v1.sections.iter() {
+      memory[section.addr as usize..][..section.data.len()].copy_from_slice(&section.data);
+    }
+    memory[0x3c4] = 0xbeef;
+    memory[0x1002] = 0xcafe;
+
+    let path = std::env::temp_dir().join(format!("wavebin-roundtrip-{}.wvm", std::process::id()));
+    let path = path.to_str().unwrap();
+    let saved = WaveVMBin::from_memory(&memory, 0, MEM_SHARED_SIZE_U);
+    write_wavevm_bin(path, &saved).unwrap();
+    let first = std::fs::read(path).unwrap();
+
+    let loaded = load_wavevm_bin(path).unwrap();
+    assert_eq!(loaded.sections, saved.sections);
+    let mut reloaded = vec![0u16; MEM_SHARED_SIZE_U];
+    for section in loaded.sections.iter() {
+      reloaded[section.addr as usize..][..section.data.len()].copy_from_slice(&section.data);
+    }
+    assert_eq!(reloaded, memory);
+
+    write_wavevm_bin(path, &loaded).unwrap();
+    let second = std::fs::read(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(first, second);
+  }
+
+  #[test]
+  fn from_memory_splits_regions() {
+    let memory = vec![0u16; MEM_SHARED_SIZE_U];
+    let bin = WaveVMBin::from_memory(&memory, 0x30, 0x3e0);
+    let layout = bin.sections.iter().map(|s| (s.addr, s.data.len(), s.flags)).collect::<Vec<_>>();
+    assert_eq!(layout, vec![
+      (0x30, 0x10, Section::DATA),
+      (0x40, 0xc0, Section::CODE),
+      (0x100, 0x200, Section::DATA),
+      (0x300, 0x100, Section::MODULE_CONFIG),
+      (0x400, 0x10, Section::DATA),
+    ]);
+  }
+
+  #[test]
+  fn random_mutations_never_panic() {
+    let seeds = [
+      v1_image(&[1, 2, 3, 4], &[5, 6, 7]),
+      v2_image(&[(0x40, Section::CODE, &[1, 2, 3]), (0x1000, Section::DATA, &[4, 5])]),
+    ];
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for _ in 0..20_000 {
+      let mut image = seeds[(rng.next() % 2) as usize].clone();
+      for _ in 0..=(rng.next() % 4) {
+        let i = (rng.next() as usize) % image.len();
+        match rng.next() % 4 {
+          0 => image[i] = rng.next() as u8,
+          1 => image[i] ^= 1 << (rng.next() % 8),
+          2 => image.truncate(i.max(1)),
+          _ => image.insert(i, rng.next() as u8),
+        }
+      }
+      let _ = parse_wavevm_bin(&image);
     }
   }
 }